@@ -6,16 +6,9 @@ use failure::Error;
 use log::trace;
 use std::path::PathBuf;
 use std::process::exit;
-use trove::{Cache, Trove, TroveFeed};
-
-/*
-Find all backup files
-loop from oldest to newest
-  load file
-  add to Trove
-load current
-add it to Trove
-*/
+use trove::{
+    format_size, Cache, Downloader, InstallerStatus, Progress, Session, Timeline, Trove, TroveFeed,
+};
 
 fn run() -> Result<(), Error> {
     env_logger::init();
@@ -55,27 +48,120 @@ fn run() -> Result<(), Error> {
                 .default_value("true")
                 .help("Filter games by whether they are downloaded"),
         )
+        .arg(
+            Arg::with_name("update")
+                .long("update")
+                .help("Merge the latest trove feed into trove.json"),
+        )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .help("Rebuild the trove's history from every trove_feed backup"),
+        )
+        .arg(
+            Arg::with_name("outdated")
+                .long("outdated")
+                .help("Show downloaded installers that have a newer version in the trove"),
+        )
+        .arg(
+            Arg::with_name("removed")
+                .long("removed")
+                .help("Show downloaded games that are no longer in the trove"),
+        )
+        .arg(
+            Arg::with_name("platforms")
+                .long("platforms")
+                .takes_value(true)
+                .help("Comma separated platforms to prefer, in order (e.g. windows,linux)"),
+        )
+        .arg(
+            Arg::with_name("download")
+                .long("download")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(0)
+                .value_name("GAME")
+                .help("Download installers for the given machine names, or every game not yet downloaded"),
+        )
+        .arg(
+            Arg::with_name("bandwidth-limit")
+                .long("bandwidth-limit")
+                .takes_value(true)
+                .value_name("KIB/S")
+                .help("Limit the download speed (0 for no limit)"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .help("Check downloaded installers against the md5 and size from the feed"),
+        )
+        .arg(
+            Arg::with_name("cache-max-size")
+                .long("cache-max-size")
+                .takes_value(true)
+                .value_name("MIB")
+                .help("Evict the least recently used cache entries and history past this size"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .help("Only use what is already cached; never touch the network"),
+        )
         .get_matches();
+    let offline = matches.is_present("offline");
     let trove_dir = dirs::home_dir()
         .expect("Unable to find home directory!")
         .join(".trove");
     let trove_games_json = trove_dir.join("trove.json");
+    let session_json = trove_dir.join("session.json");
+    let session = match session_json.exists() {
+        true => Some(Session::load(&session_json)?),
+        false => None,
+    };
+    let cache_max_size = match matches.value_of("cache-max-size") {
+        Some(max_size) => match max_size.parse::<u64>()?.checked_mul(1024 * 1024) {
+            Some(max_size) => Some(max_size),
+            None => {
+                eprintln!("--cache-max-size is too large.");
+                exit(1);
+            }
+        },
+        None => None,
+    };
+    let new_cache = || {
+        let mut cache = Cache::new(trove_dir.join("cache")).with_offline(offline);
+        if let Some(max_size) = cache_max_size {
+            cache = cache.with_max_size(max_size);
+        }
+        match &session {
+            Some(session) => cache.with_session(session.clone()),
+            None => cache,
+        }
+    };
     let mut trove = if trove_games_json.exists() {
         trace!("{} exists; loading.", &trove_games_json.display());
         let mut trove = Trove::load(&trove_dir)?;
+        let merged = trove.merge_duplicates();
+        if merged > 0 {
+            println!("Merged {} duplicate games in trove.json.", merged);
+            trove.save(&trove_games_json)?;
+        }
         // TODO: add trove.expired()
         if matches.is_present("update") {
             trace!("Updating trove.json using trove_feed.json.");
-            let cache = Cache::new(trove_dir.join("cache"));
+            let cache = new_cache();
             let mut trove_feed = TroveFeed::load(cache, &trove_dir.join("trove_feed.json"))?;
             let expired = trove_feed.expired();
             // Add these to the library before getting the next version of the feed.
             trove.add_games(trove_feed);
-            if expired {
-                let cache = Cache::new(trove_dir.join("cache"));
+            if expired && offline {
+                eprintln!("Warning: Feed is expired; not updating while offline.");
+            } else if expired {
+                let cache = new_cache();
                 trove_feed = TroveFeed::new(cache, &trove_dir)?;
                 trove.add_games(trove_feed);
             }
+            trove.save(&trove_games_json)?;
         }
         trove
     } else {
@@ -86,21 +172,126 @@ fn run() -> Result<(), Error> {
         let downloads: PathBuf = matches.value_of("downloads").unwrap().into();
         let root: PathBuf = matches.value_of("root").unwrap().into();
         let mut trove = Trove::new(&root, &downloads)?;
-        let cache = Cache::new(trove_dir.join("cache"));
+        let cache = new_cache();
         let trove_feed = TroveFeed::load(cache, &trove_dir.join("trove_feed.json"))?;
         trove.add_games(trove_feed);
         trove.save(&trove_games_json)?;
         trove
     };
+    if let Some(platforms) = matches.value_of("platforms") {
+        trove.platforms = platforms
+            .split(',')
+            .map(|platform| platform.trim().to_lowercase())
+            .filter(|platform| !platform.is_empty())
+            .collect();
+        trove.save(&trove_games_json)?;
+    }
     if matches.is_present("stray-downloads") {
         for download in trove.stray_downloads() {
             println!("{}", download.display());
         }
     }
+    if matches.is_present("history") {
+        let timeline = Timeline::replay(&trove_dir, &mut trove)?;
+        for snapshot in &timeline.snapshots {
+            println!(
+                "{}: {} added, {} removed",
+                snapshot.date,
+                snapshot.added.len(),
+                snapshot.removed.len()
+            );
+            for product in &snapshot.added {
+                println!("  + {}", product.human_name);
+            }
+            for product in &snapshot.removed {
+                println!("  - {}", product.human_name);
+            }
+        }
+        for game in timeline.games.values() {
+            println!(
+                "{}: first seen {}, last seen {}",
+                game.human_name, game.first_seen, game.last_seen
+            );
+        }
+        trove.save(&trove_games_json)?;
+    }
+    if matches.is_present("outdated") {
+        for (game, installer) in trove.outdated()? {
+            println!("{}: {}", game.human_name, installer.display());
+        }
+        // Keep the hashes worked out along the way.
+        trove.save(&trove_games_json)?;
+    }
     if matches.is_present("move-downloads") {
         trove.move_downloads();
     }
-    trove.update_download_status();
+    if matches.is_present("download") {
+        if offline {
+            eprintln!("Can't download installers while offline.");
+            exit(1);
+        }
+        let mut downloader = Downloader::new();
+        if let Some(session) = &session {
+            downloader = downloader.with_session(session.clone());
+        }
+        if let Some(limit) = matches.value_of("bandwidth-limit") {
+            match limit.parse::<u64>()?.checked_mul(1024) {
+                Some(limit) => downloader = downloader.with_bandwidth_limit(limit),
+                None => {
+                    eprintln!("--bandwidth-limit is too large.");
+                    exit(1);
+                }
+            }
+        }
+        let mut machine_names: Vec<String> = matches
+            .values_of("download")
+            .map(|names| names.map(|name| name.to_string()).collect())
+            .unwrap_or_default();
+        if machine_names.is_empty() {
+            trove.update_download_status();
+            machine_names = trove
+                .not_downloaded()
+                .iter()
+                .map(|g| g.machine_name.clone())
+                .collect();
+        }
+        for machine_name in &machine_names {
+            let result = trove.download(&downloader, machine_name, |progress| {
+                print_progress(machine_name, progress)
+            });
+            eprintln!();
+            match result {
+                Ok(_) => trove.save(&trove_games_json)?,
+                Err(err) => eprintln!("{}: {}", machine_name, err),
+            }
+        }
+    }
+    if matches.is_present("verify") {
+        trove.verify()?;
+        for game in &trove.games {
+            for (platform, status) in &game.status {
+                if *status != InstallerStatus::Downloaded && *status != InstallerStatus::Missing {
+                    println!("{} ({}): {:?}", game.human_name, platform, status);
+                }
+            }
+        }
+        println!(
+            "Verified: {}; Total: {}",
+            &trove.number_downloaded, &trove.total
+        );
+        trove.save(&trove_games_json)?;
+    } else {
+        trove.update_download_status();
+    }
+    if matches.is_present("removed") {
+        for game in trove
+            .removed()
+            .iter()
+            .filter(|g| g.is_downloaded(&trove.platforms))
+        {
+            println!("{} (last seen {})", game.human_name, game.last_seen_on);
+        }
+    }
     let mut games = trove.games.iter().map(|g| g).collect();
     if matches.is_present("downloaded") {
         let downloaded = matches.value_of("downloaded").unwrap().parse::<bool>()?;
@@ -119,6 +310,24 @@ fn run() -> Result<(), Error> {
     Ok(())
 }
 
+fn print_progress(name: &str, progress: Progress) {
+    let total = match progress.total {
+        Some(total) => format!(
+            "{} ({:.0}%)",
+            format_size(total),
+            progress.downloaded as f64 * 100.0 / total.max(1) as f64
+        ),
+        None => "?".to_string(),
+    };
+    eprint!(
+        "\r{}: {} of {} at {}/s   ",
+        name,
+        format_size(progress.downloaded),
+        total,
+        format_size(progress.rate as u64)
+    );
+}
+
 fn main() {
     match run() {
         Err(err) => {
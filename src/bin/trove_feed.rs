@@ -25,14 +25,15 @@ Integrate with Doorways launcher
 */
 extern crate trove;
 
-use clap::{App, Arg};
+use chrono::{Duration, Utc};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use dirs;
 use env_logger;
 use failure::Error;
 use std::fs;
 use std::path::PathBuf;
 use std::process::exit;
-use trove::{Cache, TroveFeed};
+use trove::{format_size, Cache, Session, TroveFeed};
 
 fn run() -> Result<(), Error> {
     env_logger::init();
@@ -54,6 +55,11 @@ fn run() -> Result<(), Error> {
                 .takes_value(true)
                 .help("Diff the titles in the current set with the ones in the specified backup"),
         )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the diff as JSON"),
+        )
         .arg(
             Arg::with_name("new")
                 .long("new")
@@ -64,11 +70,92 @@ fn run() -> Result<(), Error> {
                 .long("update")
                 .help("Update trove_feed.json"),
         )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .help("Report products that don't match the expected feed format"),
+        )
         .arg(
             Arg::with_name("cache-images")
                 .long("cache-images")
                 .help("Cache the images referenced in the Trove feed"),
         )
+        .arg(
+            Arg::with_name("concurrency")
+                .long("concurrency")
+                .takes_value(true)
+                .default_value("4")
+                .help("Number of images to download at the same time"),
+        )
+        .arg(
+            Arg::with_name("rate-limit")
+                .long("rate-limit")
+                .takes_value(true)
+                .default_value("4")
+                .help("Maximum number of requests per second, across all hosts (0 for no limit)"),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress")
+                .help("Store newly cached pages and images compressed"),
+        )
+        .arg(
+            Arg::with_name("cache-max-size")
+                .long("cache-max-size")
+                .takes_value(true)
+                .value_name("MIB")
+                .help("Evict the least recently used cache entries and history past this size"),
+        )
+        .arg(
+            Arg::with_name("cookies")
+                .long("cookies")
+                .takes_value(true)
+                .help("Netscape format cookie jar holding a logged in Humble Bundle session"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .help("Only use what is already cached; never touch the network"),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Inspect and maintain the cache in ~/.trove/cache")
+                .subcommand(
+                    SubCommand::with_name("list").about("List cached urls with their size and age"),
+                )
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Show the metadata stored for a url")
+                        .arg(Arg::with_name("url").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("invalidate")
+                        .about("Invalidate the cached urls matching a url or glob")
+                        .arg(Arg::with_name("pattern").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Check cached bodies against their recorded checksums"),
+                )
+                .subcommand(SubCommand::with_name("size").about("Print the size of the cache"))
+                .subcommand(
+                    SubCommand::with_name("compress")
+                        .about("Compress cache entries that are stored uncompressed"),
+                )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Export the cache to a tar archive")
+                        .arg(Arg::with_name("archive").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Merge a tar archive made by export into the cache")
+                        .arg(Arg::with_name("archive").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("gc").about("Remove orphaned and leftover cache files"),
+                ),
+        )
         .get_matches();
     let trove_dir: PathBuf = dirs::home_dir()
         .expect("Unable to find home directory!")
@@ -81,13 +168,42 @@ fn run() -> Result<(), Error> {
     if !cache_dir.exists() {
         fs::create_dir_all(&cache_dir)?;
     }
-    let cache = Cache::new(cache_dir);
+    let rate_limit = matches.value_of("rate-limit").unwrap().parse::<f64>()?;
+    if rate_limit < 0.0 || !rate_limit.is_finite() {
+        eprintln!("--rate-limit must be 0 or a positive number.");
+        exit(1);
+    }
+    let offline = matches.is_present("offline");
+    let mut cache = Cache::new(cache_dir)
+        .with_rate_limit(rate_limit)
+        .with_offline(offline)
+        .with_compression(matches.is_present("compress"));
+    if let Some(max_size) = matches.value_of("cache-max-size") {
+        match max_size.parse::<u64>()?.checked_mul(1024 * 1024) {
+            Some(max_size) => cache = cache.with_max_size(max_size),
+            None => {
+                eprintln!("--cache-max-size is too large.");
+                exit(1);
+            }
+        }
+    }
+    let session_json = trove_dir.join("session.json");
+    if let Some(cookies) = matches.value_of("cookies") {
+        cache = cache.with_session(Session::from_cookie_jar(&PathBuf::from(cookies))?);
+    } else if session_json.exists() {
+        cache = cache.with_session(Session::load(&session_json)?);
+    }
+    if let Some(matches) = matches.subcommand_matches("cache") {
+        return cache_command(&cache, matches);
+    }
     let mut feed = if !trove_json.exists() || matches.is_present("update") {
         TroveFeed::new(cache, &trove_dir)?
     } else {
         TroveFeed::load(cache, &trove_json)?
     };
-    if feed.expired() {
+    if feed.expired() && offline {
+        eprintln!("Warning: Feed is expired.");
+    } else if feed.expired() {
         eprintln!("Warning: Feed is expired. Run --update to correct.");
     }
     if matches.is_present("list") {
@@ -98,19 +214,122 @@ fn run() -> Result<(), Error> {
             .iter()
             .for_each(|p| println!("{}", p.human_name));
     }
+    if matches.is_present("validate") {
+        let report = feed.validate()?;
+        print!("{}", report);
+        println!(
+            "{} products with problems; {} dropped.",
+            report.products.len(),
+            report.dropped().len()
+        );
+    }
     if matches.is_present("cache-images") {
-        feed.cache_images();
+        let concurrency = matches.value_of("concurrency").unwrap().parse::<usize>()?;
+        feed.cache_images(concurrency);
     }
     if let Some(to_diff) = matches.value_of("diff") {
-        let cache = Cache::new(cache_dir);
+        let cache = Cache::new(cache_dir).with_offline(offline);
         println!("Loading old version.");
         let old = TroveFeed::load(cache, &to_diff.into())?;
         println!("Diffing");
-        feed.diff(old);
+        let diff = feed.diff(&old);
+        if matches.is_present("json") {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        } else {
+            print!("{}", diff);
+        }
+    }
+    Ok(())
+}
+
+fn cache_command(cache: &Cache, matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("list", _) => {
+            let mut entries = cache.entries()?;
+            entries.sort_by(|a, b| a.url.cmp(&b.url));
+            for entry in entries {
+                println!(
+                    "{:>10} {:>8} {}",
+                    format_size(entry.content_length),
+                    format_age(Utc::now() - entry.fetched_at),
+                    entry.url
+                );
+            }
+        }
+        ("show", Some(matches)) => {
+            let url = matches.value_of("url").unwrap();
+            match cache.metadata(url) {
+                Some(metadata) => println!("{}", serde_json::to_string_pretty(&metadata)?),
+                None => eprintln!("Not cached: {}", url),
+            }
+        }
+        ("invalidate", Some(matches)) => {
+            for url in cache.invalidate_matching(matches.value_of("pattern").unwrap())? {
+                println!("Invalidated: {}", url);
+            }
+        }
+        ("verify", _) => {
+            let mut failed = 0;
+            for entry in cache.entries()? {
+                match cache.verify(&entry.url) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        println!("Checksum mismatch: {}", entry.url);
+                        failed += 1;
+                    }
+                    Err(err) => {
+                        println!("Unable to read {}: {}", entry.url, err);
+                        failed += 1;
+                    }
+                }
+            }
+            println!("{} entries failed verification.", failed);
+        }
+        ("size", _) => {
+            println!("{}", format_size(cache.total_size()?));
+        }
+        ("compress", _) => {
+            println!("Compressed {} entries.", cache.compress_existing()?);
+        }
+        ("export", Some(matches)) => {
+            let archive = PathBuf::from(matches.value_of("archive").unwrap());
+            println!("Exported {} entries.", cache.export(&archive)?);
+        }
+        ("import", Some(matches)) => {
+            let archive = PathBuf::from(matches.value_of("archive").unwrap());
+            let report = cache.import(&archive)?;
+            println!(
+                "Imported {} entries; kept {} newer local entries.",
+                report.imported.len(),
+                report.skipped.len()
+            );
+        }
+        ("gc", _) => {
+            let report = cache.gc()?;
+            for path in &report.removed {
+                println!("Removed: {}", path.display());
+            }
+            println!(
+                "Migrated {} entries; freed {}.",
+                report.migrated,
+                format_size(report.freed)
+            );
+        }
+        _ => eprintln!("{}", matches.usage()),
     }
     Ok(())
 }
 
+fn format_age(age: Duration) -> String {
+    if age.num_days() > 0 {
+        format!("{}d", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h", age.num_hours())
+    } else {
+        format!("{}m", age.num_minutes())
+    }
+}
+
 fn main() {
     match run() {
         Err(err) => {
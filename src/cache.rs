@@ -1,33 +1,224 @@
 extern crate log;
 /// This module provides a local cache of web URLs. It is intended to be the equivalent of
-/// a browser's cache. It currently doesn't expire any entries in the cache.
+/// a browser's cache. Entries are kept until they are older than the max-age that applies
+/// to them, either the cache wide default, the first matching URL pattern policy or one
+/// passed in explicitly to `retrieve_with_max_age`. Entries with no applicable max-age
+/// never expire. Expired entries are revalidated using the `ETag` and `Last-Modified`
+/// headers saved in their metadata, so unchanged urls are not downloaded again.
+///
+/// Requests can be rate limited and transient failures (network errors, 429 and 5xx
+/// responses) are retried with exponential backoff.
+///
+/// Bodies and metadata are written to a temporary file and renamed into place, and each
+/// entry is locked while it is being fetched, so an interrupted run never leaves a truncated
+/// entry behind and several processes can safely share the same cache directory.
+///
+/// Bodies can optionally be stored gzip compressed, which is recorded in their metadata.
+/// The cache can be capped in size, counting both current entries and their history, in
+/// which case the least recently used entries and oldest versions are evicted once it grows
+/// past the cap.
+///
+/// Replaced entries are not thrown away. Whenever an entry is invalidated or refetched the
+/// previous body is moved to `history/<sha256>/<fetched at>` so older copies of a url, such
+/// as the humble bundle monthly feed, can be listed and retrieved later.
 ///
 /// TODO:
 /// - Allow for a forced overwrite of a cache entry
-/// - Enable preservation of old copies of cache entries
-/// - Provide a means to return old cached copies of entries
-/// - Use this capability to backup old copies of the humble bundle monthly feed
-extern crate sha2;
-
-use log::{debug, error, trace};
-use sha2::Digest;
+use crate::fetcher::{Fetcher, ReqwestFetcher, Response};
+use crate::session::{Session, SENSITIVE_HEADERS};
+use crate::util::{content_type_ext, glob_match, gunzip, gzip, sha256, url_path_ext, write_atomic};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use failure::Fail;
+use fs2::FileExt;
+use log::{debug, error, trace, warn};
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{Error, Read};
-use std::path::PathBuf;
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{self, Instant, SystemTime};
+use url::Url;
+
+#[derive(Debug, Fail)]
+pub enum CacheError {
+    #[fail(display = "unable to fetch {}: {}", url, message)]
+    Network { url: String, message: String },
+    #[fail(display = "{} returned HTTP {}", url, code)]
+    Status { url: String, code: u16 },
+    #[fail(display = "{}", _0)]
+    Io(#[cause] io::Error),
+    #[fail(display = "invalid url {}: {}", url, message)]
+    InvalidUrl { url: String, message: String },
+    #[fail(display = "{} is not cached", url)]
+    NotCached { url: String },
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> CacheError {
+        CacheError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CacheError {
+    fn from(err: serde_json::Error) -> CacheError {
+        CacheError::Io(err.into())
+    }
+}
+
+impl CacheError {
+    /// Whether the failure might go away if the request is tried again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            CacheError::Network { .. } => true,
+            CacheError::Status { code, .. } => *code == 429 || *code >= 500,
+            _ => false,
+        }
+    }
+}
+
+/// How many times a fetch that failed with a transient error is retried. The delay before
+/// each retry starts at `initial_backoff` and doubles up to `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: time::Duration,
+    pub max_backoff: time::Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: time::Duration::from_millis(500),
+            max_backoff: time::Duration::from_secs(30),
+        }
+    }
+}
+
+/// Spaces requests out so no more than one is started per `interval`.
+struct RateLimiter {
+    interval: time::Duration,
+    last: Mutex<Option<Instant>>,
+}
 
-fn sha256(url: &str) -> String {
-    let mut hasher = sha2::Sha256::new();
-    hasher.input(url.as_bytes());
-    hex::encode(&hasher.result())
+impl RateLimiter {
+    fn wait(&self) {
+        let mut last = self.last.lock().unwrap();
+        if let Some(last) = *last {
+            let elapsed = last.elapsed();
+            if elapsed < self.interval {
+                thread::sleep(self.interval - elapsed);
+            }
+        }
+        *last = Some(Instant::now());
+    }
+}
+
+/// The outcome of `Cache::prefetch_all` for each url.
+#[derive(Debug, Default)]
+pub struct PrefetchReport {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<(String, CacheError)>,
+}
+
+/// The urls `Cache::import` took from the archive and the ones it skipped because the
+/// cache already had a copy at least as new.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// What `Cache::gc` cleaned up.
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub removed: Vec<PathBuf>,
+    pub freed: u64,
+    pub migrated: usize,
+}
+
+/// Details of the response an entry was cached from. Stored as `<sha256>.json` next to the
+/// entry's body.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Metadata {
+    pub url: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub content_length: u64,
+    pub headers: HashMap<String, String>,
+    pub fetched_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_accessed: Option<DateTime<Utc>>,
+    pub sha256: String,
+    /// How the body is stored on disk, `gzip` or `None` when it is stored as is.
+    /// `content_length` and `sha256` always describe the uncompressed body.
+    #[serde(default)]
+    pub encoding: Option<String>,
+}
+
+impl Metadata {
+    fn is_gzip(&self) -> bool {
+        self.encoding.as_ref().map(|e| e.as_str()) == Some(GZIP)
+    }
+
+    /// Returns the file extension for the entry, preferring the content type reported by
+    /// the server over the one in the url's path.
+    pub fn extension(&self) -> Option<String> {
+        self.content_type
+            .as_ref()
+            .and_then(|content_type| content_type_ext(content_type))
+            .or_else(|| url_path_ext(self.url.clone()))
+    }
+}
+
+const VERSION_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const GZIP: &str = "gzip";
+
+/// Treats a file that is already gone as successfully removed.
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Whether `name` looks like the file name of an entry's body.
+fn is_hash(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
 }
 
 pub struct Cache {
     root: PathBuf,
+    max_age: Option<Duration>,
+    policies: Vec<(String, Duration)>,
+    fetcher: Box<dyn Fetcher>,
+    retry: RetryPolicy,
+    limiter: Option<RateLimiter>,
+    max_size: Option<u64>,
+    offline: bool,
+    compress: bool,
+    session: Option<Session>,
 }
 
 impl Cache {
     pub fn new<T: Into<PathBuf>>(root: T) -> Cache {
-        let cache = Cache { root: root.into() };
+        let cache = Cache {
+            root: root.into(),
+            max_age: None,
+            policies: Vec::new(),
+            fetcher: Box::new(ReqwestFetcher::new()),
+            retry: RetryPolicy::default(),
+            limiter: None,
+            max_size: None,
+            offline: false,
+            compress: false,
+            session: None,
+        };
         if !cache.root.exists() {
             debug!("creating: {}", cache.root.display());
             if let Err(result) = fs::create_dir_all(&cache.root) {
@@ -37,34 +228,837 @@ impl Cache {
         return cache;
     }
 
-    pub fn retrieve(&self, url: &str) -> Result<Vec<u8>, Error> {
+    /// Replaces the fetcher used to download urls, which defaults to `ReqwestFetcher`.
+    pub fn with_fetcher<F: Fetcher + 'static>(mut self, fetcher: F) -> Cache {
+        self.fetcher = Box::new(fetcher);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Cache {
+        self.retry = retry;
+        self
+    }
+
+    /// Limits the requests made through this cache to `requests_per_second`, across every
+    /// host. Zero, or anything else that isn't a positive number, means no limit.
+    pub fn with_rate_limit(mut self, requests_per_second: f64) -> Cache {
+        self.limiter = if requests_per_second > 0.0 && requests_per_second.is_finite() {
+            Some(RateLimiter {
+                interval: time::Duration::from_secs_f64(1.0 / requests_per_second),
+                last: Mutex::new(None),
+            })
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Keeps the cache from ever touching the network. Cached entries are returned no
+    /// matter how old they are and misses fail with `CacheError::NotCached`.
+    pub fn with_offline(mut self, offline: bool) -> Cache {
+        self.offline = offline;
+        self
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Sends the session's cookies and headers with requests to its hosts. They are not
+    /// part of the cache key and are never stored in an entry's metadata.
+    pub fn with_session(mut self, session: Session) -> Cache {
+        self.session = Some(session);
+        self
+    }
+
+    /// Stores newly fetched bodies gzip compressed. Bodies are decompressed transparently
+    /// when retrieved, whether or not compression is turned on.
+    pub fn with_compression(mut self, compress: bool) -> Cache {
+        self.compress = compress;
+        self
+    }
+
+    /// Caps the total size of the bodies in the cache, including the history of replaced
+    /// entries, at `max_size` bytes. Once exceeded, the least recently used entries and the
+    /// oldest versions in the history are evicted, whichever is older first.
+    pub fn with_max_size(mut self, max_size: u64) -> Cache {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets the max-age used for urls that do not match any policy.
+    pub fn with_max_age(mut self, max_age: Duration) -> Cache {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Adds a max-age policy for urls matching `pattern`, where `*` matches any run of
+    /// characters. Policies are checked in the order they were added.
+    pub fn with_policy(mut self, pattern: &str, max_age: Duration) -> Cache {
+        self.policies.push((pattern.to_string(), max_age));
+        self
+    }
+
+    pub fn max_age(&self, url: &str) -> Option<Duration> {
+        self.policies
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, url))
+            .map(|(_, max_age)| *max_age)
+            .or(self.max_age)
+    }
+
+    /// Returns the metadata recorded when `url` was fetched, if it is cached.
+    pub fn metadata(&self, url: &str) -> Option<Metadata> {
+        let hash = sha256(url);
+        if !self.root.join(&hash).exists() {
+            return None;
+        }
+        self.read_metadata(&hash)
+    }
+
+    /// Reads the sidecar for `hash`. Entries cached before sidecars existed only have a
+    /// `.url` file, so what can be is recovered from it and the body instead.
+    fn read_metadata(&self, hash: &str) -> Option<Metadata> {
+        let sidecar = self.root.join(format!("{}.json", hash));
+        if sidecar.exists() {
+            let file = fs::File::open(&sidecar).ok()?;
+            return match serde_json::from_reader(file) {
+                Ok(metadata) => Some(metadata),
+                Err(err) => {
+                    warn!("{}: {}", sidecar.display(), err);
+                    None
+                }
+            };
+        }
+        let url_file = self.root.join(format!("{}.url", hash));
+        let url = fs::read_to_string(&url_file).ok()?;
+        let modified = fs::metadata(&url_file).and_then(|m| m.modified()).ok()?;
+        let body = fs::read(self.root.join(hash)).ok()?;
+        Some(Metadata {
+            url,
+            status: 200,
+            content_type: None,
+            content_length: body.len() as u64,
+            headers: HashMap::new(),
+            fetched_at: DateTime::<Utc>::from(modified),
+            last_accessed: None,
+            sha256: sha256(&body),
+            encoding: None,
+        })
+    }
+
+    fn write_metadata(&self, metadata: &Metadata) -> Result<(), CacheError> {
+        let hash = sha256(&metadata.url);
+        let json = serde_json::to_vec_pretty(metadata)?;
+        write_atomic(&self.root.join(format!("{}.json", &hash)), &json)?;
+        let url_file = self.root.join(format!("{}.url", &hash));
+        if url_file.exists() {
+            fs::remove_file(url_file)?;
+        }
+        Ok(())
+    }
+
+    /// Returns when the entry for `url` was last fetched, if it is cached.
+    pub fn fetched_at(&self, url: &str) -> Option<DateTime<Utc>> {
+        self.metadata(url).map(|metadata| metadata.fetched_at)
+    }
+
+    pub fn expired(&self, url: &str, max_age: Option<Duration>) -> bool {
+        match (max_age, self.fetched_at(url)) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(max_age), Some(fetched_at)) => Utc::now() - fetched_at > max_age,
+        }
+    }
+
+    pub fn retrieve(&self, url: &str) -> Result<Vec<u8>, CacheError> {
+        self.retrieve_with_max_age(url, self.max_age(url))
+    }
+
+    /// Retrieves `url`, refetching it if the cached copy is older than `max_age`.
+    pub fn retrieve_with_max_age(
+        &self,
+        url: &str,
+        max_age: Option<Duration>,
+    ) -> Result<Vec<u8>, CacheError> {
         let hash = sha256(url);
-        let cached = self.root.join(&hash);
         trace!("{:?}", hash);
-        if !cached.exists() {
-            // TODO: Add cache expiration
-            debug!("caching: {}", url);
-            let mut resp = reqwest::get(url).unwrap();
-            assert!(resp.status().is_success());
-            let mut buffer = Vec::new();
-            resp.read_to_end(&mut buffer)?;
-            fs::write(&cached, buffer)?;
-            fs::write(self.root.join(format!("{}.url", &hash)), url)?;
+        if self.ensure(url, max_age)? {
+            self.limit_size(url)?;
         }
-        Ok(fs::read(cached)?)
+        self.touch(url);
+        self.read_body(url)
     }
 
-    pub fn invalidate(&self, url: &str) -> Result<(), Error> {
+    /// Reads the body cached for `url`, decompressing it if needed.
+    fn read_body(&self, url: &str) -> Result<Vec<u8>, CacheError> {
+        let body = fs::read(self.root.join(sha256(url)))?;
+        match self.metadata(url) {
+            Some(ref metadata) if metadata.is_gzip() => Ok(gunzip(&body)?),
+            _ => Ok(body),
+        }
+    }
+
+    /// Records that `url` was just used, for least recently used eviction. Failing to do
+    /// so only affects eviction order, so errors are logged rather than returned.
+    fn touch(&self, url: &str) {
+        let _lock = match self.lock(url) {
+            Ok(lock) => lock,
+            Err(err) => {
+                warn!("{}: {}", url, err);
+                return;
+            }
+        };
+        if let Some(mut metadata) = self.metadata(url) {
+            metadata.last_accessed = Some(Utc::now());
+            if let Err(err) = self.write_metadata(&metadata) {
+                warn!("{}: {}", url, err);
+            }
+        }
+    }
+
+    /// Fetches `url` unless the cached copy is still fresh. Returns whether it was fetched.
+    fn ensure(&self, url: &str, max_age: Option<Duration>) -> Result<bool, CacheError> {
+        if self.is_fresh(url, max_age) {
+            return Ok(false);
+        }
+        let _lock = self.lock(url)?;
+        // Another process may have fetched the entry while we waited for the lock.
+        if self.is_fresh(url, max_age) {
+            return Ok(false);
+        }
+        self.fetch(url)?;
+        Ok(true)
+    }
+
+    /// Takes an exclusive advisory lock on the entry for `url`, which is held until the
+    /// returned file is dropped. Every change to an entry is made while holding its lock so
+    /// that several processes can share the cache directory.
+    fn lock(&self, url: &str) -> Result<fs::File, CacheError> {
+        let file = self.lock_file(&sha256(url))?;
+        file.lock_exclusive()?;
+        Ok(file)
+    }
+
+    fn lock_file(&self, hash: &str) -> Result<fs::File, CacheError> {
+        Ok(fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(self.root.join(format!("{}.lock", hash)))?)
+    }
+
+    fn is_fresh(&self, url: &str, max_age: Option<Duration>) -> bool {
+        self.root.join(sha256(url)).exists() && !self.expired(url, max_age)
+    }
+
+    /// Makes sure every url in `urls` is cached, fetching up to `concurrency` of them at a
+    /// time. Duplicate urls are only fetched once and the rate limit still applies across
+    /// all of the workers. If the cache is capped in size, it is evicted down to the cap
+    /// once everything has been fetched.
+    pub fn prefetch_all<I, S>(&self, urls: I, concurrency: usize) -> PrefetchReport
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut seen = HashSet::new();
+        let unique: Vec<String> = urls
+            .into_iter()
+            .map(|url| url.as_ref().to_string())
+            .filter(|url| seen.insert(url.clone()))
+            .collect();
+        let queue = Mutex::new(unique.into_iter());
+        let report = Mutex::new(PrefetchReport::default());
+        let fetched = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for _ in 0..cmp::max(concurrency, 1) {
+                scope.spawn(|| loop {
+                    let url = match queue.lock().unwrap().next() {
+                        Some(url) => url,
+                        None => break,
+                    };
+                    let result = self.ensure(&url, self.max_age(&url));
+                    if let Ok(true) = result {
+                        fetched.store(true, Ordering::SeqCst);
+                    }
+                    if result.is_ok() {
+                        self.touch(&url);
+                    }
+                    let mut report = report.lock().unwrap();
+                    match result {
+                        Ok(_) => report.succeeded.push(url),
+                        Err(err) => report.failed.push((url, err)),
+                    }
+                });
+            }
+        });
+        if fetched.load(Ordering::SeqCst) {
+            if let Err(err) = self.limit_size("") {
+                warn!("unable to evict: {}", err);
+            }
+        }
+        report.into_inner().unwrap()
+    }
+
+    /// Fetches `url` into the cache. If there is a cached copy, the request is made
+    /// conditional on the validators saved with it and a 304 response only refreshes the
+    /// entry's fetch time.
+    fn fetch(&self, url: &str) -> Result<(), CacheError> {
+        Url::parse(url).map_err(|err| CacheError::InvalidUrl {
+            url: url.to_string(),
+            message: err.to_string(),
+        })?;
         let hash = sha256(url);
         let cached = self.root.join(&hash);
+        if self.offline {
+            if cached.exists() {
+                debug!("offline, using cached copy: {}", url);
+                return Ok(());
+            }
+            return Err(CacheError::NotCached {
+                url: url.to_string(),
+            });
+        }
+        let previous = self.metadata(url);
+        let mut request = HashMap::new();
+        if let Some(previous) = &previous {
+            if let Some(value) = previous.headers.get(ETAG.as_str()) {
+                request.insert(IF_NONE_MATCH.to_string(), value.clone());
+            }
+            if let Some(value) = previous.headers.get(LAST_MODIFIED.as_str()) {
+                request.insert(IF_MODIFIED_SINCE.to_string(), value.clone());
+            }
+        }
+        debug!("caching: {}", url);
+        let resp = self.send(url, &request)?;
+        if let Some(mut previous) = previous {
+            if resp.status == 304 {
+                debug!("not modified: {}", url);
+                previous.fetched_at = Utc::now();
+                return self.write_metadata(&previous);
+            }
+        }
+        if resp.status == 304 {
+            return Err(CacheError::Status {
+                url: url.to_string(),
+                code: resp.status,
+            });
+        }
+        let mut headers = resp.headers;
+        for name in SENSITIVE_HEADERS {
+            headers.remove(*name);
+        }
+        let metadata = Metadata {
+            url: url.to_string(),
+            status: resp.status,
+            content_type: headers.get(CONTENT_TYPE.as_str()).cloned(),
+            content_length: resp.body.len() as u64,
+            headers,
+            fetched_at: Utc::now(),
+            last_accessed: Some(Utc::now()),
+            sha256: sha256(&resp.body),
+            encoding: if self.compress {
+                Some(GZIP.to_string())
+            } else {
+                None
+            },
+        };
+        self.archive(url, Some(&metadata.sha256))?;
+        if self.compress {
+            write_atomic(&cached, &gzip(&resp.body)?)?;
+        } else {
+            write_atomic(&cached, &resp.body)?;
+        }
+        self.write_metadata(&metadata)?;
+        Ok(())
+    }
+
+    /// Lists the metadata of every entry in the cache.
+    pub fn entries(&self) -> Result<Vec<Metadata>, CacheError> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if !is_hash(&name) {
+                continue;
+            }
+            match self.read_metadata(&name) {
+                Some(metadata) => entries.push(metadata),
+                None => warn!("no metadata for {}", name),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the total number of bytes used by the cache directory, including the
+    /// history of replaced entries.
+    pub fn total_size(&self) -> Result<u64, CacheError> {
+        fn dir_size(dir: &Path) -> Result<u64, io::Error> {
+            let mut size = 0;
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    size += dir_size(&entry.path())?;
+                } else {
+                    size += entry.metadata()?.len();
+                }
+            }
+            Ok(size)
+        }
+        Ok(dir_size(&self.root)?)
+    }
+
+    /// Checks that the body cached for `url` still matches the checksum in its metadata.
+    pub fn verify(&self, url: &str) -> Result<bool, CacheError> {
+        let metadata = self.metadata(url).ok_or_else(|| CacheError::NotCached {
+            url: url.to_string(),
+        })?;
+        let body = self.read_body(url)?;
+        Ok(sha256(&body) == metadata.sha256)
+    }
+
+    /// Invalidates every cached url matching `pattern`, where `*` matches any run of
+    /// characters, and returns the urls that were invalidated.
+    pub fn invalidate_matching(&self, pattern: &str) -> Result<Vec<String>, CacheError> {
+        let mut invalidated = Vec::new();
+        for metadata in self.entries()? {
+            if glob_match(pattern, &metadata.url) {
+                self.invalidate(&metadata.url)?;
+                invalidated.push(metadata.url);
+            }
+        }
+        Ok(invalidated)
+    }
+
+    /// Evicts the cache down to its size cap, if it has one, sparing the entry for `keep`.
+    fn limit_size(&self, keep: &str) -> Result<(), CacheError> {
+        match self.max_size {
+            Some(max_size) => self.evict(max_size, keep),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the least recently used entries and the oldest versions in the history until
+    /// the cache's bodies, current and archived, take up no more than `max_size` bytes.
+    /// Entries in use by another thread or process, and `keep`, are skipped, as are files
+    /// that another process removed in the meantime.
+    fn evict(&self, max_size: u64, keep: &str) -> Result<(), CacheError> {
+        // (last used, size, hash, archived version or None for the current body)
+        let mut candidates: Vec<(DateTime<Utc>, u64, String, Option<PathBuf>)> = Vec::new();
+        for metadata in self.entries()? {
+            let hash = sha256(&metadata.url);
+            let size = match fs::metadata(self.root.join(&hash)) {
+                Ok(body) => body.len(),
+                Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let used = metadata.last_accessed.unwrap_or(metadata.fetched_at);
+            candidates.push((used, size, hash, None));
+        }
+        let history = self.root.join("history");
+        if history.exists() {
+            for dir in fs::read_dir(&history)? {
+                let dir = dir?;
+                if !dir.file_type()?.is_dir() {
+                    continue;
+                }
+                let hash = dir.file_name().to_string_lossy().to_string();
+                let versions = match fs::read_dir(dir.path()) {
+                    Ok(versions) => versions,
+                    Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                for version in versions {
+                    let version = version?;
+                    let file = match version.metadata() {
+                        Ok(file) => file,
+                        Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+                        Err(err) => return Err(err.into()),
+                    };
+                    let name = version.file_name().to_string_lossy().to_string();
+                    let fetched_at = match NaiveDateTime::parse_from_str(
+                        name.trim_end_matches(".gz"),
+                        VERSION_FORMAT,
+                    ) {
+                        Ok(fetched_at) => DateTime::<Utc>::from_utc(fetched_at, Utc),
+                        Err(_) => DateTime::<Utc>::from(file.modified()?),
+                    };
+                    candidates.push((fetched_at, file.len(), hash.clone(), Some(version.path())));
+                }
+            }
+        }
+        let mut size: u64 = candidates.iter().map(|(_, size, _, _)| size).sum();
+        if size <= max_size {
+            return Ok(());
+        }
+        let keep = sha256(keep);
+        candidates.sort_by_key(|(used, _, _, _)| *used);
+        for (_, length, hash, version) in candidates {
+            if size <= max_size {
+                break;
+            }
+            if hash == keep && version.is_none() {
+                continue;
+            }
+            let lock = self.lock_file(&hash)?;
+            if lock.try_lock_exclusive().is_err() {
+                continue;
+            }
+            match version {
+                Some(version) => {
+                    debug!("evicting: {}", version.display());
+                    ignore_missing(fs::remove_file(&version))?;
+                    if let Some(dir) = version.parent() {
+                        // Another thread may archive a version into it at the same time.
+                        if let Err(err) = fs::remove_dir(dir) {
+                            trace!("keeping {}: {}", dir.display(), err);
+                        }
+                    }
+                }
+                None => {
+                    debug!("evicting: {}", hash);
+                    self.remove_entry(&hash)?;
+                }
+            }
+            size -= length;
+        }
+        Ok(())
+    }
+
+    /// Removes the body and metadata for `hash`.
+    fn remove_entry(&self, hash: &str) -> Result<(), CacheError> {
+        for name in &[
+            hash.to_string(),
+            format!("{}.json", hash),
+            format!("{}.url", hash),
+        ] {
+            ignore_missing(fs::remove_file(self.root.join(name)))?;
+        }
+        Ok(())
+    }
+
+    /// Cleans up the cache directory. Entries cached before metadata sidecars existed get
+    /// one written, and bodies without any metadata, metadata and `.url` files without a
+    /// body, leftover temporary files and unused lock files are removed. If the cache is
+    /// capped in size, it is then evicted down to the cap.
+    pub fn gc(&self) -> Result<GcReport, CacheError> {
+        let mut report = GcReport::default();
+        let an_hour_ago = SystemTime::now() - time::Duration::from_secs(60 * 60);
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let (hash, ext) = match name.find('.') {
+                Some(index) => (&name[..index], &name[index + 1..]),
+                None => (name.as_str(), ""),
+            };
+            let has_body = self.root.join(hash).exists();
+            let has_sidecar = self.root.join(format!("{}.json", hash)).exists();
+            let orphaned = match ext {
+                "" if is_hash(hash) => {
+                    if !has_sidecar {
+                        match self.read_metadata(hash) {
+                            Some(metadata) => {
+                                debug!("migrating: {}", metadata.url);
+                                self.write_metadata(&metadata)?;
+                                report.migrated += 1;
+                                false
+                            }
+                            None => true,
+                        }
+                    } else {
+                        false
+                    }
+                }
+                "json" | "url" => !has_body,
+                "lock" => {
+                    let file = fs::OpenOptions::new().write(true).open(&path)?;
+                    !has_body && file.try_lock_exclusive().is_ok()
+                }
+                // Temporary files may still be being written by another process.
+                _ => name.ends_with(".tmp") && entry.metadata()?.modified()? < an_hour_ago,
+            };
+            if orphaned && path.exists() {
+                debug!("removing: {}", path.display());
+                report.freed += entry.metadata()?.len();
+                fs::remove_file(&path)?;
+                report.removed.push(path);
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            let before = self.total_size()?;
+            self.evict(max_size, "")?;
+            report.freed += before.saturating_sub(self.total_size()?);
+        }
+        Ok(report)
+    }
+
+    /// Sends a request through the fetcher, honoring the rate limit and retrying transient
+    /// failures. Any response other than a success or a 304 is returned as an error.
+    fn send(&self, url: &str, request: &HashMap<String, String>) -> Result<Response, CacheError> {
+        let mut request = request.clone();
+        if let Some(session) = &self.session {
+            session.authorize(url, &mut request);
+        }
+        let mut backoff = self.retry.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            if let Some(limiter) = &self.limiter {
+                limiter.wait();
+            }
+            let result = self.fetcher.fetch(url, &request).and_then(|resp| {
+                if (resp.status >= 200 && resp.status < 300) || resp.status == 304 {
+                    Ok(resp)
+                } else {
+                    Err(CacheError::Status {
+                        url: url.to_string(),
+                        code: resp.status,
+                    })
+                }
+            });
+            match result {
+                Err(err) if err.is_transient() && attempt < self.retry.max_retries => {
+                    warn!("{}; retrying in {:?}", err, backoff);
+                    thread::sleep(backoff);
+                    attempt += 1;
+                    backoff = cmp::min(backoff * 2, self.retry.max_backoff);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn invalidate(&self, url: &str) -> Result<(), CacheError> {
+        let _lock = self.lock(url)?;
+        self.archive(url, None)?;
+        let cached = self.root.join(sha256(url));
         if cached.exists() {
             fs::remove_file(cached)?;
         }
         Ok(())
     }
 
-    pub fn force_retrieve(&self, url: &str) -> Result<Vec<u8>, Error> {
-        self.invalidate(url)?;
-        self.retrieve(url)
+    /// Copies the current body for `url` into its history directory. The body stays in
+    /// place so readers never see the entry disappear while it is being replaced. Nothing
+    /// is archived if `replacement`, the sha256 of the new body, matches the current one.
+    fn archive(&self, url: &str, replacement: Option<&str>) -> Result<(), CacheError> {
+        let hash = sha256(url);
+        let cached = self.root.join(&hash);
+        if !cached.exists() {
+            return Ok(());
+        }
+        let metadata = self.metadata(url);
+        if let (Some(metadata), Some(replacement)) = (&metadata, replacement) {
+            if metadata.sha256 == replacement {
+                debug!("unchanged, not archiving: {}", url);
+                return Ok(());
+            }
+        }
+        let fetched_at = metadata
+            .as_ref()
+            .map(|m| m.fetched_at)
+            .unwrap_or_else(Utc::now);
+        let history = self.root.join("history").join(&hash);
+        fs::create_dir_all(&history)?;
+        let mut name = fetched_at.format(VERSION_FORMAT).to_string();
+        if metadata.map(|m| m.is_gzip()).unwrap_or(false) {
+            name.push_str(".gz");
+        }
+        let archived = history.join(name);
+        debug!("archiving: {} to {}", url, archived.display());
+        if fs::hard_link(&cached, &archived).is_err() {
+            fs::copy(&cached, &archived)?;
+        }
+        Ok(())
+    }
+
+    /// Lists when each stored copy of `url` was fetched, oldest first. The current entry,
+    /// if any, is the last one listed.
+    pub fn versions(&self, url: &str) -> Result<Vec<DateTime<Utc>>, CacheError> {
+        let history = self.root.join("history").join(sha256(url));
+        let mut versions = Vec::new();
+        if history.exists() {
+            for entry in fs::read_dir(history)? {
+                let name = entry?.file_name();
+                let name = name.to_string_lossy();
+                let version = name.trim_end_matches(".gz");
+                match NaiveDateTime::parse_from_str(version, VERSION_FORMAT) {
+                    Ok(version) => versions.push(DateTime::<Utc>::from_utc(version, Utc)),
+                    Err(_) => warn!("unexpected file in history: {}", name),
+                }
+            }
+        }
+        versions.sort();
+        if let Some(fetched_at) = self.fetched_at(url) {
+            versions.push(fetched_at);
+        }
+        Ok(versions)
+    }
+
+    /// Returns the copy of `url` fetched at `version`, as listed by `versions`.
+    pub fn retrieve_version(
+        &self,
+        url: &str,
+        version: &DateTime<Utc>,
+    ) -> Result<Vec<u8>, CacheError> {
+        let hash = sha256(url);
+        let name = version.format(VERSION_FORMAT).to_string();
+        if let Some(fetched_at) = self.fetched_at(url) {
+            if fetched_at.format(VERSION_FORMAT).to_string() == name {
+                return self.read_body(url);
+            }
+        }
+        let history = self.root.join("history").join(&hash);
+        let archived = history.join(&name);
+        if archived.exists() {
+            return Ok(fs::read(archived)?);
+        }
+        let compressed = history.join(format!("{}.gz", &name));
+        if compressed.exists() {
+            return Ok(gunzip(&fs::read(compressed)?)?);
+        }
+        Err(CacheError::Io(io::Error::new(
+            ErrorKind::NotFound,
+            format!("no version of {} fetched at {}", url, version),
+        )))
+    }
+
+    /// Returns the newest copy of `url` that had been fetched by `date`, if there is one.
+    pub fn retrieve_as_of(
+        &self,
+        url: &str,
+        date: &DateTime<Utc>,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        let versions = self.versions(url)?;
+        match versions.iter().filter(|version| *version <= date).last() {
+            Some(version) => Ok(Some(self.retrieve_version(url, version)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Revalidates `url` with the server regardless of its age.
+    pub fn force_retrieve(&self, url: &str) -> Result<Vec<u8>, CacheError> {
+        let lock = self.lock(url)?;
+        self.fetch(url)?;
+        drop(lock);
+        self.limit_size(url)?;
+        self.read_body(url)
+    }
+
+    /// Writes every entry's body and metadata to a tar archive at `path`. Bodies are
+    /// written as they are stored, so compressed entries stay compressed.
+    pub fn export(&self, path: &Path) -> Result<usize, CacheError> {
+        let mut builder = tar::Builder::new(fs::File::create(path)?);
+        let mut count = 0;
+        for metadata in self.entries()? {
+            let hash = sha256(&metadata.url);
+            let _lock = self.lock(&metadata.url)?;
+            let body = fs::read(self.root.join(&hash))?;
+            let json = serde_json::to_vec_pretty(&metadata)?;
+            for (name, data) in &[(hash.clone(), body), (format!("{}.json", &hash), json)] {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(metadata.fetched_at.timestamp() as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, name, data.as_slice())?;
+            }
+            count += 1;
+        }
+        builder.finish()?;
+        Ok(count)
+    }
+
+    /// Merges the entries in a tar archive written by `export` into the cache. When a url
+    /// is cached on both sides the copy fetched most recently is kept, with the other one
+    /// going into the url's history. Bodies are streamed to temporary files as they are
+    /// read and merged as soon as their metadata turns up, so the archive is never held in
+    /// memory.
+    pub fn import(&self, path: &Path) -> Result<ImportReport, CacheError> {
+        let mut bodies: HashMap<String, PathBuf> = HashMap::new();
+        let mut sidecars: HashMap<String, Metadata> = HashMap::new();
+        let mut report = ImportReport::default();
+        let mut archive = tar::Archive::new(fs::File::open(path)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().to_string();
+            if name.ends_with(".json") {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                let metadata = serde_json::from_slice::<Metadata>(&data)?;
+                sidecars.insert(sha256(&metadata.url), metadata);
+            } else if is_hash(&name) {
+                let temp = self
+                    .root
+                    .join(format!("{}.{}-import.tmp", &name, std::process::id()));
+                io::copy(&mut entry, &mut fs::File::create(&temp)?)?;
+                bodies.insert(name, temp);
+            } else {
+                warn!("unexpected file in {}: {}", path.display(), name);
+                continue;
+            }
+            let ready: Vec<String> = sidecars
+                .keys()
+                .filter(|hash| bodies.contains_key(*hash))
+                .cloned()
+                .collect();
+            for hash in ready {
+                let metadata = sidecars.remove(&hash).unwrap();
+                let body = bodies.remove(&hash).unwrap();
+                self.import_entry(metadata, &body, &mut report)?;
+            }
+        }
+        for (_, metadata) in sidecars {
+            warn!("no body for {} in {}", metadata.url, path.display());
+        }
+        for (name, body) in bodies {
+            warn!("no metadata for {} in {}", name, path.display());
+            fs::remove_file(body)?;
+        }
+        Ok(report)
+    }
+
+    /// Moves `body`, a temporary file holding the body for `metadata`, into the cache unless
+    /// the cache already has a copy at least as new.
+    fn import_entry(
+        &self,
+        metadata: Metadata,
+        body: &Path,
+        report: &mut ImportReport,
+    ) -> Result<(), CacheError> {
+        let _lock = self.lock(&metadata.url)?;
+        if let Some(existing) = self.metadata(&metadata.url) {
+            if existing.fetched_at >= metadata.fetched_at {
+                fs::remove_file(body)?;
+                report.skipped.push(metadata.url);
+                return Ok(());
+            }
+        }
+        debug!("importing: {}", metadata.url);
+        self.archive(&metadata.url, Some(&metadata.sha256))?;
+        fs::rename(body, self.root.join(sha256(&metadata.url)))?;
+        self.write_metadata(&metadata)?;
+        report.imported.push(metadata.url);
+        Ok(())
+    }
+
+    /// Compresses every entry that is stored uncompressed, for caches created before
+    /// compression was turned on. Returns the number of entries compressed.
+    pub fn compress_existing(&self) -> Result<usize, CacheError> {
+        let mut count = 0;
+        for mut metadata in self.entries()? {
+            if metadata.is_gzip() {
+                continue;
+            }
+            let _lock = self.lock(&metadata.url)?;
+            let cached = self.root.join(sha256(&metadata.url));
+            debug!("compressing: {}", metadata.url);
+            write_atomic(&cached, &gzip(&fs::read(&cached)?)?)?;
+            metadata.encoding = Some(GZIP.to_string());
+            self.write_metadata(&metadata)?;
+            count += 1;
+        }
+        Ok(count)
     }
 }
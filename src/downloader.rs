@@ -0,0 +1,235 @@
+/// This module downloads installers into the trove's root. Data is written to a `.part`
+/// file next to the destination, so an interrupted download can be resumed with an HTTP
+/// Range request, and the file is only renamed into place once it is complete.
+use crate::session::Session;
+use failure::Fail;
+use log::{debug, info};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Fail)]
+pub enum DownloadError {
+    #[fail(display = "unable to download {}: {}", url, message)]
+    Network { url: String, message: String },
+    #[fail(display = "{} returned HTTP {}", url, code)]
+    Status { url: String, code: u16 },
+    #[fail(
+        display = "{} sent bytes from {} when asked for {}",
+        url, start, offset
+    )]
+    UnexpectedRange {
+        url: String,
+        start: u64,
+        offset: u64,
+    },
+    #[fail(display = "{} ended after {} of {} bytes", url, downloaded, total)]
+    Incomplete {
+        url: String,
+        downloaded: u64,
+        total: u64,
+    },
+    #[fail(display = "{}", _0)]
+    Io(#[cause] io::Error),
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(err: io::Error) -> DownloadError {
+        DownloadError::Io(err)
+    }
+}
+
+/// How far along a download is. `total` is `None` if the server didn't say.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    /// Bytes per second since this attempt started, not counting resumed data.
+    pub rate: f64,
+}
+
+pub struct Downloader {
+    client: reqwest::Client,
+    session: Option<Session>,
+    bandwidth_limit: Option<u64>,
+}
+
+impl Downloader {
+    pub fn new() -> Downloader {
+        Downloader {
+            client: reqwest::Client::new(),
+            session: None,
+            bandwidth_limit: None,
+        }
+    }
+
+    /// Sends the session's cookies and headers with requests to the hosts it applies to.
+    pub fn with_session(mut self, session: Session) -> Downloader {
+        self.session = Some(session);
+        self
+    }
+
+    /// Caps the download speed at `bytes_per_second`. Zero means no limit.
+    pub fn with_bandwidth_limit(mut self, bytes_per_second: u64) -> Downloader {
+        self.bandwidth_limit = match bytes_per_second {
+            0 => None,
+            limit => Some(limit),
+        };
+        self
+    }
+
+    /// Where the incomplete download of `dest` is kept.
+    pub fn part_path(dest: &Path) -> PathBuf {
+        let mut name = dest.file_name().unwrap_or_default().to_os_string();
+        name.push(".part");
+        dest.with_file_name(name)
+    }
+
+    /// Downloads `url` to `dest`, picking up where an earlier attempt left off if its
+    /// `.part` file is still around. If the server can't pick up from there, the `.part`
+    /// file is thrown away and the download starts over. `progress` is called as data
+    /// arrives. Returns the size of the finished file.
+    pub fn download<F>(&self, url: &str, dest: &Path, mut progress: F) -> Result<u64, DownloadError>
+    where
+        F: FnMut(Progress),
+    {
+        let part = Downloader::part_path(dest);
+        let mut offset = match fs::metadata(&part) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        let mut headers = HashMap::new();
+        if let Some(session) = &self.session {
+            session.authorize(url, &mut headers);
+        }
+        let mut request = self.client.get(url);
+        for (name, value) in &headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if offset > 0 {
+            debug!("Resuming {} at {} bytes.", url, offset);
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let mut resp = request.send().map_err(|err| DownloadError::Network {
+            url: url.to_string(),
+            message: err.to_string(),
+        })?;
+        let total = match resp.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let (start, total) = content_range(&resp);
+                match start {
+                    Some(start) if start == offset => {}
+                    Some(start) if offset > 0 => {
+                        info!(
+                            "{} resumed at {} bytes, not {}; starting over.",
+                            url, start, offset
+                        );
+                        fs::remove_file(&part)?;
+                        return self.download(url, dest, progress);
+                    }
+                    Some(start) => {
+                        return Err(DownloadError::UnexpectedRange {
+                            url: url.to_string(),
+                            start,
+                            offset,
+                        })
+                    }
+                    None => {}
+                }
+                total.or_else(|| resp.content_length().map(|length| length + offset))
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+                if content_range(&resp).1 == Some(offset) {
+                    // Everything was downloaded before the previous attempt was cut off.
+                    fs::rename(&part, dest)?;
+                    return Ok(offset);
+                }
+                info!("{} is not {} bytes long; starting over.", url, offset);
+                fs::remove_file(&part)?;
+                return self.download(url, dest, progress);
+            }
+            status if status.is_success() => {
+                if offset > 0 {
+                    info!("{} can't be resumed; starting over.", url);
+                    offset = 0;
+                }
+                resp.content_length()
+            }
+            status => {
+                return Err(DownloadError::Status {
+                    url: url.to_string(),
+                    code: status.as_u16(),
+                })
+            }
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&part)?;
+        let start = Instant::now();
+        let mut received = 0;
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            let read = resp.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            received += read as u64;
+            if let Some(limit) = self.bandwidth_limit {
+                let expected = Duration::from_secs_f64(received as f64 / limit as f64);
+                let elapsed = start.elapsed();
+                if expected > elapsed {
+                    thread::sleep(expected - elapsed);
+                }
+            }
+            progress(Progress {
+                downloaded: offset + received,
+                total,
+                rate: received as f64 / start.elapsed().as_secs_f64().max(0.001),
+            });
+        }
+        file.sync_all()?;
+        let downloaded = offset + received;
+        if let Some(total) = total {
+            if downloaded < total {
+                return Err(DownloadError::Incomplete {
+                    url: url.to_string(),
+                    downloaded,
+                    total,
+                });
+            }
+        }
+        fs::rename(&part, dest)?;
+        Ok(downloaded)
+    }
+}
+
+/// Reads the `Content-Range` header, `bytes <start>-<end>/<total>` or `bytes */<total>`,
+/// returning the first byte sent and the size of the whole file where they are given.
+fn content_range(resp: &reqwest::Response) -> (Option<u64>, Option<u64>) {
+    let range = match resp
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+    {
+        Some(range) => range.trim_start_matches("bytes").trim(),
+        None => return (None, None),
+    };
+    let mut parts = range.splitn(2, '/');
+    let start = parts
+        .next()
+        .and_then(|bytes| bytes.split('-').next())
+        .and_then(|start| start.parse().ok());
+    let total = parts.next().and_then(|total| total.parse().ok());
+    (start, total)
+}
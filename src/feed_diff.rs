@@ -0,0 +1,248 @@
+/// This module compares two snapshots of the trove feed. Products are matched up by
+/// `machine_name`, so a product whose human name changed shows up as changed rather than
+/// as one title removed and another added.
+use crate::trove::Installer;
+use crate::trove_feed::{Download, Product};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProductSummary {
+    pub machine_name: String,
+    pub human_name: String,
+}
+
+impl From<&Product> for ProductSummary {
+    fn from(p: &Product) -> ProductSummary {
+        ProductSummary {
+            machine_name: p.machine_name.clone(),
+            human_name: p.human_name.clone(),
+        }
+    }
+}
+
+/// A single field that differs between the two snapshots of a product. `old` is `None`
+/// when the field was added and `new` is `None` when it was removed.
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProductChange {
+    pub machine_name: String,
+    pub human_name: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A platform's installer that changed between the two snapshots, meaning local copies
+/// of the old one are outdated.
+#[derive(Serialize, Debug, Clone)]
+pub struct InstallerUpdate {
+    pub machine_name: String,
+    pub human_name: String,
+    pub platform: String,
+    pub old: Installer,
+    pub new: Installer,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct FeedDiff {
+    pub added: Vec<ProductSummary>,
+    pub removed: Vec<ProductSummary>,
+    pub changed: Vec<ProductChange>,
+    pub installer_updates: Vec<InstallerUpdate>,
+}
+
+impl FeedDiff {
+    /// Compares the products in `older` with the ones in `newer`.
+    pub fn new(older: &[Product], newer: &[Product]) -> FeedDiff {
+        let older: BTreeMap<&str, &Product> =
+            older.iter().map(|p| (p.machine_name.as_str(), p)).collect();
+        let newer: BTreeMap<&str, &Product> =
+            newer.iter().map(|p| (p.machine_name.as_str(), p)).collect();
+        let mut diff = FeedDiff::default();
+        for (machine_name, product) in &newer {
+            match older.get(machine_name) {
+                None => diff.added.push((*product).into()),
+                Some(old) => {
+                    let changes = product_changes(old, product);
+                    diff.installer_updates
+                        .extend(installer_updates(old, product));
+                    if !changes.is_empty() {
+                        diff.changed.push(ProductChange {
+                            machine_name: machine_name.to_string(),
+                            human_name: product.human_name.clone(),
+                            changes,
+                        });
+                    }
+                }
+            }
+        }
+        for (machine_name, product) in &older {
+            if !newer.contains_key(machine_name) {
+                diff.removed.push((*product).into());
+            }
+        }
+        diff
+    }
+
+    /// `changed` already lists every installer update as field changes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn compare<T: PartialEq + ToString>(
+    changes: &mut Vec<FieldChange>,
+    field: &str,
+    old: Option<T>,
+    new: Option<T>,
+) {
+    if old != new {
+        changes.push(FieldChange {
+            field: field.to_string(),
+            old: old.map(|v| v.to_string()),
+            new: new.map(|v| v.to_string()),
+        });
+    }
+}
+
+fn product_changes(old: &Product, new: &Product) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    compare(
+        &mut changes,
+        "human_name",
+        Some(&old.human_name),
+        Some(&new.human_name),
+    );
+    compare(
+        &mut changes,
+        "description",
+        Some(&old.description_text),
+        Some(&new.description_text),
+    );
+    compare(&mut changes, "image", Some(&old.image), Some(&new.image));
+    compare(&mut changes, "logo", old.logo.as_ref(), new.logo.as_ref());
+    compare(
+        &mut changes,
+        "screenshots",
+        Some(old.carousel_content.screenshot.join(" ")),
+        Some(new.carousel_content.screenshot.join(" ")),
+    );
+    compare(
+        &mut changes,
+        "thumbnails",
+        Some(old.carousel_content.thumbnail.join(" ")),
+        Some(new.carousel_content.thumbnail.join(" ")),
+    );
+    let platforms: BTreeSet<&String> = old.downloads.keys().chain(new.downloads.keys()).collect();
+    for platform in platforms {
+        download_changes(
+            &mut changes,
+            platform,
+            old.downloads.get(platform),
+            new.downloads.get(platform),
+        );
+    }
+    changes
+}
+
+fn installer_updates(old: &Product, new: &Product) -> Vec<InstallerUpdate> {
+    let mut updates = Vec::new();
+    for (platform, download) in &new.downloads {
+        if let Some(previous) = old.downloads.get(platform) {
+            let (old_installer, new_installer) = (Installer::from(previous), download.into());
+            if old_installer != new_installer {
+                updates.push(InstallerUpdate {
+                    machine_name: new.machine_name.clone(),
+                    human_name: new.human_name.clone(),
+                    platform: platform.clone(),
+                    old: old_installer,
+                    new: new_installer,
+                });
+            }
+        }
+    }
+    updates.sort_by(|a, b| a.platform.cmp(&b.platform));
+    updates
+}
+
+fn download_changes(
+    changes: &mut Vec<FieldChange>,
+    platform: &str,
+    old: Option<&Download>,
+    new: Option<&Download>,
+) {
+    let field = |name: &str| format!("downloads.{}.{}", platform, name);
+    match (old, new) {
+        (Some(old), Some(new)) => {
+            compare(changes, &field("name"), Some(&old.name), Some(&new.name));
+            compare(
+                changes,
+                &field("url"),
+                Some(&old.url.web),
+                Some(&new.url.web),
+            );
+            compare(
+                changes,
+                &field("file_size"),
+                Some(old.file_size),
+                Some(new.file_size),
+            );
+            compare(changes, &field("md5"), Some(&old.md5), Some(&new.md5));
+        }
+        // The whole platform was added or removed.
+        (old, new) => compare(
+            changes,
+            &field("name"),
+            old.map(|d| &d.name),
+            new.map(|d| &d.name),
+        ),
+    }
+}
+
+impl fmt::Display for FeedDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Added titles:")?;
+        writeln!(f, "-------------")?;
+        for product in &self.added {
+            writeln!(f, "{}", product.human_name)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Deleted titles:")?;
+        writeln!(f, "---------------")?;
+        for product in &self.removed {
+            writeln!(f, "{}", product.human_name)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Updated installers:")?;
+        writeln!(f, "-------------------")?;
+        for update in &self.installer_updates {
+            writeln!(
+                f,
+                "{} ({}): {} -> {}",
+                update.human_name, update.platform, update.old.file, update.new.file
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Changed titles:")?;
+        writeln!(f, "---------------")?;
+        for product in &self.changed {
+            writeln!(f, "{}", product.human_name)?;
+            for change in &product.changes {
+                writeln!(
+                    f,
+                    "  {}: {} -> {}",
+                    change.field,
+                    change.old.as_ref().map(|v| v.as_str()).unwrap_or("(none)"),
+                    change.new.as_ref().map(|v| v.as_str()).unwrap_or("(none)")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+/// This module provides the ways a `Cache` can fetch urls. `ReqwestFetcher` goes out to
+/// the network, `FixtureFetcher` serves responses from a directory on disk and
+/// `RecordingFetcher` saves whatever another fetcher returns into such a directory so it
+/// can be replayed later without a network connection.
+use crate::cache::CacheError;
+use crate::session::SENSITIVE_HEADERS;
+use crate::util::sha256;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct Response {
+    pub status: u16,
+    /// Header names are lower case.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Fetchers are shared between the worker threads of `Cache::prefetch_all`.
+pub trait Fetcher: Send + Sync {
+    /// Requests `url`, sending along the given request headers.
+    fn fetch(&self, url: &str, headers: &HashMap<String, String>) -> Result<Response, CacheError>;
+}
+
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+}
+
+impl ReqwestFetcher {
+    pub fn new() -> ReqwestFetcher {
+        ReqwestFetcher {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Fetcher for ReqwestFetcher {
+    fn fetch(&self, url: &str, headers: &HashMap<String, String>) -> Result<Response, CacheError> {
+        let mut request = self.client.get(url);
+        for (name, value) in headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let mut resp = request.send().map_err(|err| CacheError::Network {
+            url: url.to_string(),
+            message: err.to_string(),
+        })?;
+        let mut body = Vec::new();
+        resp.read_to_end(&mut body)?;
+        Ok(Response {
+            status: resp.status().as_u16(),
+            headers: resp
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            body,
+        })
+    }
+}
+
+/// An entry in a fixture directory's `index.json`. `file` is relative to the directory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fixture {
+    pub file: PathBuf,
+    #[serde(default = "ok")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+fn ok() -> u16 {
+    200
+}
+
+impl Fixture {
+    fn response(&self, root: &Path) -> Result<Response, CacheError> {
+        Ok(Response {
+            status: self.status,
+            headers: self.headers.clone(),
+            body: fs::read(root.join(&self.file))?,
+        })
+    }
+}
+
+/// Serves responses from a directory containing an `index.json` that maps urls to
+/// fixtures. Urls missing from the index are reported as a 404.
+pub struct FixtureFetcher {
+    root: PathBuf,
+    index: HashMap<String, Fixture>,
+}
+
+impl FixtureFetcher {
+    pub fn new<T: Into<PathBuf>>(root: T) -> Result<FixtureFetcher, CacheError> {
+        let root = root.into();
+        let index_file = root.join("index.json");
+        let index = if index_file.exists() {
+            serde_json::from_reader(fs::File::open(index_file)?)?
+        } else {
+            HashMap::new()
+        };
+        Ok(FixtureFetcher { root, index })
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.index.contains_key(url)
+    }
+}
+
+impl Fetcher for FixtureFetcher {
+    fn fetch(&self, url: &str, _headers: &HashMap<String, String>) -> Result<Response, CacheError> {
+        let fixture = self.index.get(url).ok_or_else(|| CacheError::Status {
+            url: url.to_string(),
+            code: 404,
+        })?;
+        debug!("replaying: {}", url);
+        fixture.response(&self.root)
+    }
+}
+
+/// Replays urls already recorded in `root` and fetches everything else with `inner`,
+/// recording the response so the next run can replay it.
+pub struct RecordingFetcher<F: Fetcher> {
+    inner: F,
+    root: PathBuf,
+    index: Mutex<HashMap<String, Fixture>>,
+}
+
+impl<F: Fetcher> RecordingFetcher<F> {
+    pub fn new<T: Into<PathBuf>>(inner: F, root: T) -> Result<RecordingFetcher<F>, CacheError> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        let index = FixtureFetcher::new(&root)?.index;
+        Ok(RecordingFetcher {
+            inner,
+            root,
+            index: Mutex::new(index),
+        })
+    }
+}
+
+impl<F: Fetcher> Fetcher for RecordingFetcher<F> {
+    fn fetch(&self, url: &str, headers: &HashMap<String, String>) -> Result<Response, CacheError> {
+        let mut index = self.index.lock().unwrap();
+        if let Some(fixture) = index.get(url) {
+            debug!("replaying: {}", url);
+            return fixture.response(&self.root);
+        }
+        let response = self.inner.fetch(url, headers)?;
+        debug!("recording: {}", url);
+        let mut fixture = Fixture {
+            file: PathBuf::from(sha256(url)),
+            status: response.status,
+            headers: response.headers.clone(),
+        };
+        for name in SENSITIVE_HEADERS {
+            fixture.headers.remove(*name);
+        }
+        fs::write(self.root.join(&fixture.file), &response.body)?;
+        index.insert(url.to_string(), fixture);
+        let file = fs::File::create(self.root.join("index.json"))?;
+        serde_json::to_writer_pretty(file, &*index)?;
+        Ok(response)
+    }
+}
@@ -0,0 +1,161 @@
+/// This module reconstructs the history of the trove from the `trove_feed-YYYY-MM-DD.json`
+/// backups kept by `TroveFeed::backup`. Replaying them from oldest to newest gives the
+/// first and last date each game was seen and what was added and removed in each snapshot.
+use crate::cache::Cache;
+use crate::feed_diff::{FeedDiff, ProductSummary};
+use crate::trove::Trove;
+use crate::trove_feed::{Product, TroveFeed};
+use chrono::NaiveDate;
+use failure::Error;
+use log::{debug, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// A feed backup and the date it was taken.
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub date: NaiveDate,
+    pub path: PathBuf,
+}
+
+impl Backup {
+    /// Finds every feed backup in `dir`, oldest first.
+    pub fn find_all(dir: &PathBuf) -> Result<Vec<Backup>, Error> {
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if !name.starts_with("trove_feed-") {
+                continue;
+            }
+            let date = name
+                .trim_start_matches("trove_feed-")
+                .trim_end_matches(".gz")
+                .trim_end_matches(".json");
+            match NaiveDate::parse_from_str(date, DATE_FORMAT) {
+                Ok(date) => backups.push(Backup { date, path }),
+                Err(_) => warn!("Skipping unexpected backup name: {}", name),
+            }
+        }
+        backups.sort_by_key(|backup| backup.date);
+        Ok(backups)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GameHistory {
+    pub machine_name: String,
+    pub human_name: String,
+    pub first_seen: NaiveDate,
+    pub last_seen: NaiveDate,
+}
+
+/// The titles added and removed since the previous snapshot.
+#[derive(Serialize, Debug, Clone)]
+pub struct SnapshotChanges {
+    pub date: NaiveDate,
+    pub added: Vec<ProductSummary>,
+    pub removed: Vec<ProductSummary>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Timeline {
+    pub games: BTreeMap<String, GameHistory>,
+    pub snapshots: Vec<SnapshotChanges>,
+}
+
+impl Timeline {
+    /// Replays every backup in `dir` from oldest to newest. Games that have since left the
+    /// trove are added to `trove` as they were last seen, and then `last_seen_on` and
+    /// `removed_from_trove` are filled in for every game. Games already in `trove` are
+    /// otherwise left alone, since the backups are older than what it holds.
+    pub fn replay(dir: &PathBuf, trove: &mut Trove) -> Result<Timeline, Error> {
+        let mut timeline = Timeline::default();
+        let mut previous: Vec<Product> = Vec::new();
+        let mut last_seen: BTreeMap<String, Product> = BTreeMap::new();
+        for backup in Backup::find_all(dir)? {
+            debug!("Replaying {}.", backup.path.display());
+            let feed = TroveFeed::load(Cache::new(dir.join("cache")), &backup.path)?;
+            if !feed.is_complete() {
+                // Products it dropped would look like they left the trove.
+                warn!(
+                    "Skipping {}; some products couldn't be read.",
+                    backup.path.display()
+                );
+                continue;
+            }
+            // The backup's name is the day it was taken, which may be after the feed was
+            // generated.
+            let date = NaiveDate::parse_from_str(&feed.date(), DATE_FORMAT).unwrap_or(backup.date);
+            let products = feed.products().clone();
+            timeline.add_snapshot(date, &previous, &products);
+            for product in &products {
+                last_seen.insert(product.machine_name.clone(), product.clone());
+            }
+            previous = products;
+        }
+        for (machine_name, product) in &last_seen {
+            if !trove.games.iter().any(|g| &g.machine_name == machine_name) {
+                trove.games.push(product.into());
+            }
+        }
+        timeline.apply(trove);
+        Ok(timeline)
+    }
+
+    fn add_snapshot(&mut self, date: NaiveDate, previous: &[Product], products: &[Product]) {
+        for product in products {
+            let history = self
+                .games
+                .entry(product.machine_name.clone())
+                .or_insert_with(|| GameHistory {
+                    machine_name: product.machine_name.clone(),
+                    human_name: product.human_name.clone(),
+                    first_seen: date,
+                    last_seen: date,
+                });
+            history.human_name = product.human_name.clone();
+            history.last_seen = date;
+        }
+        let diff = FeedDiff::new(previous, products);
+        self.snapshots.push(SnapshotChanges {
+            date,
+            added: diff.added,
+            removed: diff.removed,
+        });
+    }
+
+    /// The date of the newest snapshot.
+    pub fn latest(&self) -> Option<NaiveDate> {
+        self.snapshots.last().map(|snapshot| snapshot.date)
+    }
+
+    /// Updates `last_seen_on` and `removed_from_trove` for the games in `trove` the
+    /// timeline knows about. `last_seen_on` is only ever moved forward, and games last seen
+    /// before the newest snapshot are marked as removed, but a game already marked as
+    /// removed by a newer feed stays that way.
+    pub fn apply(&self, trove: &mut Trove) {
+        let latest = match self.latest() {
+            Some(latest) => latest.format(DATE_FORMAT).to_string(),
+            None => return,
+        };
+        for game in trove.games.iter_mut() {
+            if let Some(history) = self.games.get(&game.machine_name) {
+                let last_seen = history.last_seen.format(DATE_FORMAT).to_string();
+                if last_seen > game.last_seen_on {
+                    game.last_seen_on = last_seen;
+                }
+                if game.last_seen_on < latest {
+                    game.removed_from_trove = true;
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,19 @@
 mod cache;
+mod downloader;
+mod feed_diff;
+mod fetcher;
+mod history;
+mod session;
 mod trove;
 mod trove_feed;
 mod util;
 
-pub use cache::Cache;
-pub use trove::{Trove, TroveGame};
-pub use trove_feed::TroveFeed;
+pub use cache::{Cache, CacheError, GcReport, ImportReport, Metadata, PrefetchReport, RetryPolicy};
+pub use downloader::{DownloadError, Downloader, Progress};
+pub use feed_diff::{FeedDiff, FieldChange, InstallerUpdate, ProductChange, ProductSummary};
+pub use fetcher::{Fetcher, Fixture, FixtureFetcher, RecordingFetcher, ReqwestFetcher, Response};
+pub use history::{Backup, GameHistory, SnapshotChanges, Timeline};
+pub use session::Session;
+pub use trove::{FileHash, Installer, InstallerStatus, Trove, TroveGame};
+pub use trove_feed::{ProductReport, TroveFeed, TroveFeedError, ValidationReport};
+pub use util::format_size;
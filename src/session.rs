@@ -0,0 +1,109 @@
+/// This module holds the credentials of a logged in Humble Bundle session. They are only
+/// ever sent to the session's hosts and are never written into the cache.
+use failure::Error;
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+/// The session cookie set by humblebundle.com when logging in.
+pub const SESSION_COOKIE: &str = "_simpleauth_sess";
+
+/// Response headers that carry credentials and must not be persisted.
+pub const SENSITIVE_HEADERS: &[&str] = &["set-cookie"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Session {
+    #[serde(default)]
+    pub cookies: HashMap<String, String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_hosts")]
+    pub hosts: Vec<String>,
+}
+
+fn default_hosts() -> Vec<String> {
+    vec!["humblebundle.com".to_string()]
+}
+
+impl Session {
+    /// Loads a session from a JSON config file of the form
+    /// `{"cookies": {"_simpleauth_sess": "..."}, "headers": {"User-Agent": "..."}}`.
+    pub fn load(path: &Path) -> Result<Session, Error> {
+        Ok(serde_json::from_reader(fs::File::open(path)?)?)
+    }
+
+    /// Loads the cookies for the default hosts from a Netscape format cookie jar, such as
+    /// the `cookies.txt` exported by browser extensions.
+    pub fn from_cookie_jar(path: &Path) -> Result<Session, Error> {
+        let mut session = Session {
+            cookies: HashMap::new(),
+            headers: HashMap::new(),
+            hosts: default_hosts(),
+        };
+        for line in fs::read_to_string(path)?.lines() {
+            // Cookies marked HttpOnly are prefixed with `#HttpOnly_` instead of being
+            // commented out.
+            let line = line.trim_start_matches("#HttpOnly_");
+            if line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                continue;
+            }
+            let domain = fields[0].trim_start_matches('.');
+            if session.hosts.iter().any(|host| matches_host(host, domain)) {
+                session
+                    .cookies
+                    .insert(fields[5].to_string(), fields[6].to_string());
+            }
+        }
+        if !session.cookies.contains_key(SESSION_COOKIE) {
+            warn!("No {} cookie found in {}.", SESSION_COOKIE, path.display());
+        }
+        Ok(session)
+    }
+
+    /// Whether the session's credentials should be sent along with a request for `url`.
+    /// They are only ever sent over https.
+    pub fn applies_to(&self, url: &str) -> bool {
+        match Url::parse(url) {
+            Ok(ref url) if url.scheme() != "https" => false,
+            Ok(url) => match url.host_str() {
+                Some(domain) => self.hosts.iter().any(|host| matches_host(host, domain)),
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// Adds the session's cookies and headers to `request` if they apply to `url`.
+    pub fn authorize(&self, url: &str, request: &mut HashMap<String, String>) {
+        if self.applies_to(url) {
+            self.apply(request);
+        }
+    }
+
+    /// Adds the session's cookies and headers to `request`.
+    pub fn apply(&self, request: &mut HashMap<String, String>) {
+        for (name, value) in &self.headers {
+            request.insert(name.to_lowercase(), value.clone());
+        }
+        if !self.cookies.is_empty() {
+            let mut cookies: Vec<String> = self
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            cookies.sort();
+            request.insert("cookie".to_string(), cookies.join("; "));
+        }
+    }
+}
+
+fn matches_host(host: &str, domain: &str) -> bool {
+    domain == host || domain.ends_with(&format!(".{}", host))
+}
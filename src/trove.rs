@@ -1,8 +1,9 @@
 use crate::cache::Cache;
-use crate::trove_feed::{Product, TroveFeed};
-use crate::util::{extension, url_path_ext};
-use log::warn;
-use serde::{Deserialize, Serialize};
+use crate::downloader::{DownloadError, Downloader, Progress};
+use crate::trove_feed::{Download, Product, TroveFeed};
+use crate::util::{extension, md5_file, url_path_ext};
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::io::Error;
@@ -14,7 +15,9 @@ pub struct TroveGame {
     pub human_name: String,
     pub description: String,
     pub date_added: u32,
-    pub downloaded: bool, // eventually HashMap
+    /// Whether the installer for each platform is in the trove's root.
+    #[serde(deserialize_with = "downloaded_by_platform")]
+    pub downloaded: HashMap<String, bool>,
     pub installed: bool,
     pub executable: PathBuf,
     pub download_urls: HashMap<String, String>,
@@ -26,6 +29,138 @@ pub struct TroveGame {
     pub trailer: Option<String>,
     pub last_seen_on: String,
     pub removed_from_trove: bool,
+    #[serde(default)]
+    pub installers: HashMap<String, Installer>,
+    /// Installers that were replaced by a newer version in the feed, oldest first.
+    #[serde(default)]
+    pub previous_installers: HashMap<String, Vec<Installer>>,
+    /// Names the game was previously listed under in the feed.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// The result of the last `Trove::verify`, by platform.
+    #[serde(default)]
+    pub status: HashMap<String, InstallerStatus>,
+}
+
+/// What `Trove::verify` found in `root` for an installer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallerStatus {
+    /// Matches the feed's md5 and file size.
+    Downloaded,
+    Missing,
+    /// Smaller than the feed's file size.
+    Truncated,
+    /// Doesn't match the feed or any previous version of the installer.
+    Corrupt,
+    /// A previous version of the installer.
+    Outdated,
+}
+
+/// A cached md5 for a file in `root`, valid as long as its size and mtime don't change.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileHash {
+    pub size: u64,
+    pub modified: u64,
+    pub md5: String,
+}
+
+/// The version of an installer listed in the feed, by platform.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Installer {
+    pub file: String,
+    pub file_size: u64,
+    pub md5: String,
+}
+
+impl From<&Download> for Installer {
+    fn from(d: &Download) -> Installer {
+        Installer {
+            file: PathBuf::from(&d.url.web)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            file_size: d.file_size,
+            md5: d.md5.clone(),
+        }
+    }
+}
+
+/// Reads `downloaded`, which used to be a single flag for the Windows installer.
+fn downloaded_by_platform<'de, D>(deserializer: D) -> Result<HashMap<String, bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Downloaded {
+        Flag(bool),
+        ByPlatform(HashMap<String, bool>),
+    }
+    Ok(match Downloaded::deserialize(deserializer)? {
+        Downloaded::Flag(downloaded) => {
+            let mut by_platform = HashMap::new();
+            by_platform.insert("windows".to_string(), downloaded);
+            by_platform
+        }
+        Downloaded::ByPlatform(downloaded) => downloaded,
+    })
+}
+
+/// The platforms to prefer, in order, when a game has installers for more than one.
+pub fn default_platforms() -> Vec<String> {
+    vec![
+        "windows".to_string(),
+        "linux".to_string(),
+        "mac".to_string(),
+    ]
+}
+
+/// The status of a local copy of `installer` that is `size` bytes long. Without an md5
+/// on both sides only the size is checked, and a feed without a size, which reads as
+/// zero, accepts any size.
+fn installer_status(
+    installer: &Installer,
+    previous: &[Installer],
+    size: u64,
+    md5: Option<String>,
+) -> InstallerStatus {
+    match md5 {
+        Some(ref md5) if !installer.md5.is_empty() => {
+            if *md5 == installer.md5 {
+                return InstallerStatus::Downloaded;
+            }
+            if previous.iter().any(|p| p.md5 == *md5) {
+                return InstallerStatus::Outdated;
+            }
+        }
+        _ => {
+            if installer.file_size == 0 || size == installer.file_size {
+                return InstallerStatus::Downloaded;
+            }
+            if previous
+                .iter()
+                .any(|p| p.file_size != 0 && p.file_size == size)
+            {
+                return InstallerStatus::Outdated;
+            }
+        }
+    }
+    if size < installer.file_size {
+        InstallerStatus::Truncated
+    } else {
+        InstallerStatus::Corrupt
+    }
+}
+
+/// Seconds since the epoch that the file was last modified.
+fn modified(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
 /*
@@ -34,26 +169,25 @@ pub struct TroveGame {
  */
 impl From<&Product> for TroveGame {
     fn from(p: &Product) -> TroveGame {
-        let mut download_urls = HashMap::<String, String>::new();
-        download_urls.insert(
-            "windows".to_string(),
-            p.downloads["windows"].url.web.clone(),
-        );
+        let download_urls: HashMap<String, String> = p
+            .downloads
+            .iter()
+            .map(|(platform, download)| (platform.clone(), download.url.web.clone()))
+            .collect();
         TroveGame {
             machine_name: p.machine_name.clone(),
             human_name: p.human_name.clone(),
             description: p.description_text.clone(),
             date_added: p.date_added,
-            downloaded: false,
+            downloaded: HashMap::new(),
             installed: false,
             executable: "".to_string().into(),
             downloads: download_urls
                 .iter()
-                .map(|(o, u)| {
-                    (
-                        o.clone(),
-                        PathBuf::from(PathBuf::from(u).file_name().unwrap()).clone(),
-                    )
+                .filter_map(|(o, u)| {
+                    PathBuf::from(u)
+                        .file_name()
+                        .map(|name| (o.clone(), PathBuf::from(name)))
                 })
                 .collect(),
             download_urls: download_urls,
@@ -64,6 +198,91 @@ impl From<&Product> for TroveGame {
             trailer: p.youtube_link.clone(),
             last_seen_on: "".to_string(),
             removed_from_trove: false,
+            installers: p
+                .downloads
+                .iter()
+                .map(|(platform, download)| (platform.clone(), download.into()))
+                .collect(),
+            previous_installers: HashMap::new(),
+            aliases: Vec::new(),
+            status: HashMap::new(),
+        }
+    }
+}
+
+impl TroveGame {
+    /// Whether the installer for any of `platforms` has been downloaded.
+    pub fn is_downloaded(&self, platforms: &[String]) -> bool {
+        platforms
+            .iter()
+            .any(|platform| self.downloaded.get(platform) == Some(&true))
+    }
+
+    /// Whether the game has an installer for any of `platforms`.
+    pub fn is_available(&self, platforms: &[String]) -> bool {
+        platforms
+            .iter()
+            .any(|platform| self.downloads.contains_key(platform))
+    }
+
+    /// The platform and installer file to use, following the order of `platforms`.
+    pub fn preferred_download(&self, platforms: &[String]) -> Option<(&String, &PathBuf)> {
+        platforms
+            .iter()
+            .filter_map(|platform| self.downloads.get_key_value(platform))
+            .next()
+    }
+
+    /// Updates the name of the game, keeping the previous name as an alias.
+    fn rename(&mut self, human_name: &str) {
+        if self.human_name == human_name {
+            return;
+        }
+        info!("{} was renamed to {}", self.human_name, human_name);
+        let previous = std::mem::replace(&mut self.human_name, human_name.to_string());
+        if !self.aliases.contains(&previous) {
+            self.aliases.push(previous);
+        }
+        self.aliases.retain(|alias| alias != human_name);
+    }
+
+    /// Folds `other`, an older entry for the same game, into this one.
+    fn merge(&mut self, other: TroveGame) {
+        let mut other = other;
+        for alias in other.aliases.drain(..).chain(Some(other.human_name)) {
+            if alias != self.human_name && !self.aliases.contains(&alias) {
+                self.aliases.push(alias);
+            }
+        }
+        for (platform, downloaded) in other.downloaded {
+            *self.downloaded.entry(platform).or_insert(false) |= downloaded;
+        }
+        self.installed |= other.installed;
+        if self.executable.as_os_str().is_empty() {
+            self.executable = other.executable;
+        }
+        for (platform, installers) in other.previous_installers {
+            let previous = self
+                .previous_installers
+                .entry(platform)
+                .or_insert_with(Vec::new);
+            for installer in installers {
+                if !previous.contains(&installer) {
+                    previous.push(installer);
+                }
+            }
+        }
+        for (platform, installer) in other.installers {
+            if self.installers.get(&platform) == Some(&installer) {
+                continue;
+            }
+            let previous = self
+                .previous_installers
+                .entry(platform)
+                .or_insert_with(Vec::new);
+            if !previous.contains(&installer) {
+                previous.push(installer);
+            }
         }
     }
 }
@@ -75,7 +294,7 @@ trait VecTroveGame {
 impl VecTroveGame for Vec<TroveGame> {
     fn find_mut(&mut self, other: &TroveGame) -> Option<&mut TroveGame> {
         for game in self.into_iter() {
-            if game.human_name == other.human_name {
+            if game.machine_name == other.machine_name {
                 return Some(game);
             }
         }
@@ -90,6 +309,12 @@ pub struct Trove {
     pub number_downloaded: u32,
     pub total: u32,
     pub games: Vec<TroveGame>,
+    /// Preferred platforms, in order. Only these count towards download status.
+    #[serde(default = "default_platforms")]
+    pub platforms: Vec<String>,
+    /// Hashes of installers in `root`, by file name.
+    #[serde(default)]
+    pub hashes: HashMap<String, FileHash>,
     //pub installed_games: Vec<String>,
     //pub downloaded_games: Vec<String>,
     //pub not_downloaded_games: Vec<String>,
@@ -104,6 +329,36 @@ impl Trove {
         Ok(trove)
     }
 
+    /// Merges games that share a machine_name, left behind by renames before games were
+    /// matched on machine_name. The most recently seen entry is kept, or the last one listed
+    /// if they were seen on the same day or never. Returns the number of entries that were
+    /// merged away.
+    pub fn merge_duplicates(&mut self) -> usize {
+        let mut games: Vec<TroveGame> = Vec::with_capacity(self.games.len());
+        let mut merged = 0;
+        for game in self.games.drain(..) {
+            match games.find_mut(&game) {
+                Some(existing) => {
+                    info!(
+                        "Merging duplicate entries for {}: {} and {}",
+                        game.machine_name, existing.human_name, game.human_name
+                    );
+                    // Later entries were added later, so they win ties.
+                    if game.last_seen_on >= existing.last_seen_on {
+                        let older = std::mem::replace(existing, game);
+                        existing.merge(older);
+                    } else {
+                        existing.merge(game);
+                    }
+                    merged += 1;
+                }
+                None => games.push(game),
+            }
+        }
+        self.games = games;
+        merged
+    }
+
     pub fn from(root: &PathBuf, downloads: &PathBuf, feed: TroveFeed) -> Result<Trove, Error> {
         let mut trove = Trove::new(root, downloads)?;
         trove.add_games(feed);
@@ -117,20 +372,31 @@ impl Trove {
             number_downloaded: 0,
             total: 0,
             games: Vec::new(),
+            platforms: default_platforms(),
+            hashes: HashMap::new(),
         };
         assert!(trove.root.exists());
         assert!(trove.downloads.exists());
         Ok(trove)
     }
 
+    /// Merges the games in `feed` into the trove. Games in the feed are stamped with the
+    /// feed's date, and games missing from it are marked as removed from the trove unless
+    /// the feed dropped products it couldn't read. Games last seen in a newer feed are left
+    /// as they are.
     pub fn add_games(&mut self, feed: TroveFeed) {
+        let date = feed.date();
+        let complete = feed.is_complete();
         for product in feed.products() {
-            let game: TroveGame = product.into();
+            let mut game: TroveGame = product.into();
             let entry = self.games.find_mut(&game);
             match entry {
                 Some(existing) => {
-                    existing.machine_name = game.machine_name;
-                    existing.human_name = game.human_name;
+                    // An older feed, such as a backup, says nothing about the game now.
+                    if date < existing.last_seen_on {
+                        continue;
+                    }
+                    existing.rename(&game.human_name);
                     existing.description = game.description;
                     existing.date_added = game.date_added;
                     existing.executable = game.executable;
@@ -141,41 +407,282 @@ impl Trove {
                     existing.screenshots = game.screenshots;
                     existing.thumbnails = game.thumbnails;
                     existing.trailer = game.trailer;
-                    // computed: downloaded, installed, last_seen_on, removed_from_trove
+                    for (platform, installer) in game.installers.iter_mut() {
+                        if let Some(previous) = existing.installers.get(platform) {
+                            // A null or mistyped md5 or size reads as empty, which says
+                            // nothing about the installer, so keep what was known.
+                            if installer.md5.is_empty() {
+                                installer.md5 = previous.md5.clone();
+                            }
+                            if installer.file_size == 0 {
+                                installer.file_size = previous.file_size;
+                            }
+                            if previous != installer {
+                                info!(
+                                    "{} {} installer changed: {} -> {}",
+                                    existing.human_name, platform, previous.file, installer.file
+                                );
+                                let previous_installers = existing
+                                    .previous_installers
+                                    .entry(platform.clone())
+                                    .or_insert_with(Vec::new);
+                                if !previous_installers.contains(previous) {
+                                    previous_installers.push(previous.clone());
+                                }
+                            }
+                        }
+                    }
+                    existing.installers = game.installers;
+                    existing.last_seen_on = date.clone();
+                    existing.removed_from_trove = false;
+                    // computed: downloaded, installed
+                }
+                None => {
+                    let mut game = game;
+                    game.last_seen_on = date.clone();
+                    self.games.push(game);
                 }
-                None => self.games.push(game),
             }
         }
+        if !complete {
+            warn!("Some products in the feed couldn't be read; not checking for removed games.");
+            return;
+        }
+        for game in self.games.iter_mut() {
+            if game.last_seen_on < date && !game.removed_from_trove {
+                info!("{} is no longer in the trove.", game.human_name);
+                game.removed_from_trove = true;
+            }
+        }
+    }
+
+    /// Games that are no longer available from the trove.
+    pub fn removed(&self) -> Vec<&TroveGame> {
+        (&self.games)
+            .iter()
+            .filter(|g| g.removed_from_trove)
+            .collect()
     }
 
+    /// Works out which installers are in `root`. Installers are checked against the feed's
+    /// file size, and against its md5 when `verify` has already hashed the file, so an
+    /// installer found corrupt, truncated or outdated isn't counted as downloaded.
     pub fn update_download_status(&mut self) {
+        if let Err(err) = self.check_installers(false) {
+            warn!("Unable to check installers: {}", err);
+        }
+        println!(
+            "Downloaded: {}; Total: {}",
+            &self.number_downloaded, &self.total
+        );
+    }
+
+    /// Returns the local installers in `root` that have been replaced by a newer version
+    /// in the feed and need to be downloaded again. An installer replaced under the same
+    /// file name is told apart from the current one by its size and md5.
+    pub fn outdated(&mut self) -> Result<Vec<(&TroveGame, PathBuf)>, Error> {
+        let root = &self.root;
+        let hashes = &mut self.hashes;
+        let mut outdated: Vec<(usize, PathBuf)> = Vec::new();
+        for (index, game) in self.games.iter().enumerate() {
+            for (platform, previous) in &game.previous_installers {
+                let current = game.installers.get(platform);
+                for installer in previous {
+                    let path = root.join(&installer.file);
+                    if installer.file.is_empty()
+                        || !path.exists()
+                        || outdated.iter().any(|(_, p)| p == &path)
+                    {
+                        continue;
+                    }
+                    if let Some(current) = current.filter(|c| c.file == installer.file) {
+                        let hash = Trove::hash(hashes, &path)?;
+                        if hash.size == current.file_size
+                            && (current.md5.is_empty() || hash.md5 == current.md5)
+                        {
+                            continue;
+                        }
+                    }
+                    outdated.push((index, path));
+                }
+            }
+        }
+        let games = &self.games;
+        Ok(outdated
+            .into_iter()
+            .map(|(index, path)| (&games[index], path))
+            .collect())
+    }
+
+    /// Downloads the installer for the first of the preferred platforms `machine_name` has
+    /// one for into `root`, and marks it as downloaded. Returns `None` if there is no such
+    /// game or it has no installer for the preferred platforms.
+    pub fn download<F>(
+        &mut self,
+        downloader: &Downloader,
+        machine_name: &str,
+        progress: F,
+    ) -> Result<Option<PathBuf>, DownloadError>
+    where
+        F: FnMut(Progress),
+    {
+        let platforms = &self.platforms;
+        let game = match self
+            .games
+            .iter_mut()
+            .find(|g| g.machine_name == machine_name)
+        {
+            Some(game) => game,
+            None => {
+                warn!("{} is not in the trove.", machine_name);
+                return Ok(None);
+            }
+        };
+        let (platform, installer) = match game.preferred_download(platforms) {
+            Some((platform, installer)) => (platform.clone(), installer.clone()),
+            None => {
+                warn!(
+                    "{} has no installer for {}.",
+                    game.human_name,
+                    platforms.join(", ")
+                );
+                return Ok(None);
+            }
+        };
+        let url = match game.download_urls.get(&platform) {
+            Some(url) => url.clone(),
+            None => return Ok(None),
+        };
+        let dest = self.root.join(&installer);
+        downloader.download(&url, &dest, progress)?;
+        info!("Downloaded {} to {}.", game.human_name, dest.display());
+        game.downloads.insert(platform.clone(), installer);
+        game.downloaded.insert(platform, true);
+        Ok(Some(dest))
+    }
+
+    /// Checks every installer in `root` against the md5 and file size from the feed and
+    /// records the result in each game's `status`. Only verified installers count as
+    /// downloaded. Hashes are cached by size and mtime, so unchanged files aren't read again.
+    pub fn verify(&mut self) -> Result<(), Error> {
+        self.check_installers(true)
+    }
+
+    /// Sets each game's `status` and `downloaded`. Files are only hashed when `hash` is
+    /// set; otherwise just the hashes already cached are used.
+    fn check_installers(&mut self, hash: bool) -> Result<(), Error> {
         let mut count = 0;
+        let mut total = 0;
+        let root = &self.root;
+        let hashes = &mut self.hashes;
         for game in self.games.iter_mut() {
-            let installer = game.downloads["windows"].to_str().unwrap();
-            game.downloaded = self.root.join(installer).exists();
-            if game.downloaded {
+            game.status.clear();
+            game.downloaded.clear();
+            for (platform, file) in &game.downloads {
+                let path = root.join(file);
+                let previous = game
+                    .previous_installers
+                    .get(platform)
+                    .map(|previous| previous.as_slice())
+                    .unwrap_or(&[]);
+                let status = if !path.exists() {
+                    let outdated = previous
+                        .iter()
+                        .any(|p| !p.file.is_empty() && root.join(&p.file).exists());
+                    match outdated {
+                        true => InstallerStatus::Outdated,
+                        false => InstallerStatus::Missing,
+                    }
+                } else {
+                    let size = fs::metadata(&path)?.len();
+                    let md5 = match hash {
+                        true => Some(Trove::hash(hashes, &path)?.md5),
+                        false => Trove::cached_hash(hashes, &path)?.map(|h| h.md5),
+                    };
+                    match game.installers.get(platform) {
+                        Some(installer) => installer_status(installer, previous, size, md5),
+                        // Nothing to check it against.
+                        None => InstallerStatus::Downloaded,
+                    }
+                };
+                if hash
+                    && status != InstallerStatus::Downloaded
+                    && status != InstallerStatus::Missing
+                {
+                    warn!("{} {} installer is {:?}", game.human_name, platform, status);
+                }
+                game.downloaded
+                    .insert(platform.clone(), status == InstallerStatus::Downloaded);
+                game.status.insert(platform.clone(), status);
+            }
+            if game.is_downloaded(&self.platforms) {
                 count += 1;
             }
+            if game.is_available(&self.platforms) {
+                total += 1;
+            }
         }
         self.number_downloaded = count;
-        self.total = self.games.len() as u32;
-        println!(
-            "Downloaded: {}; Total: {}",
-            &self.number_downloaded, &self.total
-        );
+        self.total = total;
+        Ok(())
+    }
+
+    /// Returns the hash of `path` from `hashes`, if the file hasn't changed since it was
+    /// last hashed.
+    fn cached_hash(
+        hashes: &HashMap<String, FileHash>,
+        path: &Path,
+    ) -> Result<Option<FileHash>, Error> {
+        let metadata = fs::metadata(path)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(hashes
+            .get(&name)
+            .filter(|hash| hash.size == metadata.len() && hash.modified == modified(&metadata))
+            .cloned())
+    }
+
+    /// Returns the hash of `path`, from `hashes` if the file hasn't changed since it was
+    /// last hashed.
+    fn hash(hashes: &mut HashMap<String, FileHash>, path: &Path) -> Result<FileHash, Error> {
+        if let Some(hash) = Trove::cached_hash(hashes, path)? {
+            return Ok(hash);
+        }
+        let metadata = fs::metadata(path)?;
+        info!("Hashing {}.", path.display());
+        let hash = FileHash {
+            size: metadata.len(),
+            modified: modified(&metadata),
+            md5: md5_file(path)?,
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        hashes.insert(name, hash.clone());
+        Ok(hash)
     }
 
     pub fn downloaded(&self) -> Vec<&TroveGame> {
-        (&self.games).iter().filter(|g| g.downloaded).collect()
+        (&self.games)
+            .iter()
+            .filter(|g| g.is_downloaded(&self.platforms))
+            .collect()
     }
 
+    /// Games with an installer for one of the preferred platforms that haven't been
+    /// downloaded yet.
     pub fn not_downloaded(&self) -> Vec<&TroveGame> {
-        (&self.games).iter().filter(|g| !g.downloaded).collect()
+        (&self.games)
+            .iter()
+            .filter(|g| g.is_available(&self.platforms) && !g.is_downloaded(&self.platforms))
+            .collect()
     }
 
     /// Save current trove game metadata to disk
     /// Pull down copies of all game related images
-    /// TODO: Throttle or rate limit this method
     pub fn cache_all_metadata(&self) -> Result<(), Error> {
         /*let metadata_root = self.root.join("metadata/");
         assert!(metadata_root.exists());
@@ -219,7 +726,13 @@ impl Trove {
     }
 
     pub fn format(&self, g: &TroveGame) -> String {
-        format!("{} {} {}", g.date_added, g.human_name, g.downloaded)
+        let platforms: Vec<&str> = self
+            .platforms
+            .iter()
+            .filter(|platform| g.downloaded.get(*platform) == Some(&true))
+            .map(|platform| platform.as_str())
+            .collect();
+        format!("{} {} {}", g.date_added, g.human_name, platforms.join(","))
     }
 
     pub fn stray_downloads(&self) -> Vec<PathBuf> {
@@ -227,13 +740,9 @@ impl Trove {
         assert!(downloads.exists());
         (&self.games)
             .iter()
-            .filter_map(|game| {
-                let installer = Path::new(&game.downloads["windows"])
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap();
-                let full_installer_path = downloads.join(&installer);
+            .flat_map(|game| game.downloads.values())
+            .filter_map(|installer| {
+                let full_installer_path = downloads.join(installer.file_name()?);
                 match full_installer_path.exists() {
                     true => Some(full_installer_path),
                     false => None,
@@ -1,14 +1,22 @@
 /// This module handles the deserialization of the humble bundle monthly trove metadata feed.
 /// It provides operations that deal with the contents of the feed itself.
 use crate::cache::Cache;
+use crate::feed_diff::FeedDiff;
+use crate::history::DATE_FORMAT;
+use crate::util::{gunzip, gzip};
 use chrono::{NaiveDateTime, Utc};
-use failure::Error;
+use failure::{Error, Fail};
 use log::{debug, info, warn};
-use select::{document::Document, predicate::Attr};
-use serde::Deserialize;
+use select::{
+    document::Document,
+    predicate::{Attr, Name},
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 use std::collections::HashMap;
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::str;
@@ -22,10 +30,12 @@ pub struct TimerOptions {
     pub next_addition_time: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct Url {
+    #[serde(default, deserialize_with = "or_default")]
     pub web: String,
+    #[serde(default, deserialize_with = "or_default")]
     pub bittorrent: Option<String>,
 }
 
@@ -33,25 +43,53 @@ pub struct Url {
 #[serde(rename_all = "snake_case")]
 pub struct Download {
     //pub uploaded_at: Option<String>,
+    #[serde(default, deserialize_with = "or_default")]
     pub machine_name: String,
+    #[serde(default, deserialize_with = "or_default")]
     pub name: String,
+    #[serde(default, deserialize_with = "or_default")]
     pub url: Url,
+    #[serde(default, deserialize_with = "or_default")]
     pub file_size: u64,
     //pub small: Option<u8>,
+    #[serde(default, deserialize_with = "or_default")]
     pub md5: String,
     //pub sha1: Option<String>,
+    #[serde(default, deserialize_with = "or_default")]
     pub size: Option<String>,
     //pub timestamp: Option<u64>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+const DOWNLOAD_FIELDS: &[Field] = &[
+    ("machine_name", valid::<String>),
+    ("name", valid::<String>),
+    ("url", valid::<Url>),
+    ("file_size", valid::<u64>),
+    ("md5", valid::<String>),
+    ("size", valid::<Option<String>>),
+];
+
+#[derive(Debug, Deserialize, Clone, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct CarouselContent {
+    #[serde(default, deserialize_with = "or_default")]
     pub youtube_link: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "or_default")]
     pub thumbnail: Vec<String>,
+    #[serde(default, deserialize_with = "or_default")]
     pub screenshot: Vec<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
 }
 
+const CAROUSEL_CONTENT_FIELDS: &[Field] = &[
+    ("youtube-link", valid::<Option<Vec<String>>>),
+    ("thumbnail", valid::<Vec<String>>),
+    ("screenshot", valid::<Vec<String>>),
+];
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Publisher {
@@ -66,53 +104,254 @@ pub struct Developer {
     pub developer_url: Option<String>,
 }
 
+/// Only `machine_name` and `human_name` are required. Everything else falls back to a
+/// default when it is missing, null or of an unexpected type, so a change to a
+/// non-essential field doesn't fail the whole update, and fields this model doesn't know
+/// about are kept in `extra`.
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Product {
+    #[serde(default, deserialize_with = "or_default")]
     pub all_access: bool,
+    #[serde(default, deserialize_with = "or_default")]
     pub background_image: Option<String>, // can be null
+    #[serde(default, deserialize_with = "or_default")]
     pub background_color: Option<String>, // can be null
+    #[serde(default, deserialize_with = "or_default")]
     pub carousel_content: CarouselContent,
+    #[serde(default, deserialize_with = "or_default")]
     pub date_added: u32,
+    #[serde(default, deserialize_with = "or_default")]
     pub description_text: String,
+    #[serde(default, deserialize_with = "or_default")]
     pub developers: Option<Vec<Developer>>,
+    #[serde(default, deserialize_with = "tolerant_downloads")]
     pub downloads: HashMap<String, Download>,
     pub human_name: String,
+    #[serde(default, deserialize_with = "or_default")]
     pub humble_original: Option<bool>, // can be null
+    #[serde(default, deserialize_with = "or_default")]
     pub image: String,
+    #[serde(default, deserialize_with = "or_default")]
     pub logo: Option<String>,
     #[serde(rename = "machine_name")]
     pub machine_name: String,
+    #[serde(default)]
     pub marketing_blurb: Value, //Map {text, style} or String,
+    #[serde(default, deserialize_with = "or_default")]
     pub popularity: u16,
-    pub publishers: Value,                  // can be null Vec<Publisher>,
+    #[serde(default)]
+    pub publishers: Value, // can be null Vec<Publisher>,
+    #[serde(default, deserialize_with = "or_default")]
     pub trove_showcase_css: Option<String>, // can be null
-    pub youtube_link: Option<String>,       // can be null
+    #[serde(default, deserialize_with = "or_default")]
+    pub youtube_link: Option<String>, // can be null
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
 }
 
-trait ProductVec {
-    fn contains(&self, machine_name: &str) -> bool;
+// Required fields accept any value here; products missing them are reported as dropped.
+const PRODUCT_FIELDS: &[Field] = &[
+    ("all-access", valid::<bool>),
+    ("background-image", valid::<Option<String>>),
+    ("background-color", valid::<Option<String>>),
+    ("carousel-content", valid::<CarouselContent>),
+    ("date-added", valid::<u32>),
+    ("description-text", valid::<String>),
+    ("developers", valid::<Option<Vec<Developer>>>),
+    ("downloads", valid::<HashMap<String, Value>>),
+    ("human-name", valid::<Value>),
+    ("humble-original", valid::<Option<bool>>),
+    ("image", valid::<String>),
+    ("logo", valid::<Option<String>>),
+    ("machine_name", valid::<Value>),
+    ("marketing-blurb", valid::<Value>),
+    ("popularity", valid::<u16>),
+    ("publishers", valid::<Value>),
+    ("trove-showcase-css", valid::<Option<String>>),
+    ("youtube-link", valid::<Option<String>>),
+];
+
+/// A field this model reads and whether a value can be read as its type.
+type Field = (&'static str, fn(&Value) -> bool);
+
+/// Whether `value` can be read as a `T`. Fields that can't are replaced by their default.
+fn valid<T: DeserializeOwned>(value: &Value) -> bool {
+    serde_json::from_value::<T>(value.clone()).is_ok()
 }
 
-impl ProductVec for Vec<Product> {
-    fn contains(&self, human_name: &str) -> bool {
-        for product in self.iter() {
-            if product.human_name == human_name {
-                return true;
+/// Deserializes a non-essential field, falling back to its default if it is null or can't
+/// be deserialized as `T`.
+fn or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).unwrap_or_default())
+}
+
+/// Deserializes a product's downloads, skipping the platforms that can't be deserialized.
+fn tolerant_downloads<'de, D>(deserializer: D) -> Result<HashMap<String, Download>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = match Value::deserialize(deserializer)? {
+        Value::Object(values) => values,
+        _ => return Ok(HashMap::new()),
+    };
+    Ok(values
+        .into_iter()
+        .filter_map(|(platform, value)| match serde_json::from_value(value) {
+            Ok(download) => Some((platform, download)),
+            Err(err) => {
+                warn!("Skipping {} download: {}", platform, err);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Deserializes a list of products, skipping the ones that can't be deserialized rather
+/// than failing the whole list. `TroveFeed::validate` reports the dropped products, and a
+/// feed with dropped products is never used to decide which games left the trove.
+fn tolerant_products<'de, D>(deserializer: D) -> Result<Vec<Product>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Vec::<Value>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .filter_map(|value| match serde_json::from_value(value) {
+            Ok(product) => Some(product),
+            Err(err) => {
+                warn!("Skipping product: {}", err);
+                None
+            }
+        })
+        .collect())
+}
+
+/// The fields of one product that were missing, not recognized, or null or of the wrong
+/// type and so replaced by their default, or why it couldn't be read at all, in which case
+/// it was dropped from the feed. Nested fields are reported as `<field>.<key>`.
+#[derive(Debug, Default)]
+pub struct ProductReport {
+    pub machine_name: String,
+    pub missing: Vec<String>,
+    pub unexpected: Vec<String>,
+    pub defaulted: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl ProductReport {
+    fn new(value: &Value) -> ProductReport {
+        let mut report = ProductReport::default();
+        report.machine_name = value["machine_name"]
+            .as_str()
+            .unwrap_or("<unknown>")
+            .to_string();
+        report.check("", value, PRODUCT_FIELDS);
+        report.check(
+            "carousel-content.",
+            &value["carousel-content"],
+            CAROUSEL_CONTENT_FIELDS,
+        );
+        if let Some(downloads) = value["downloads"].as_object() {
+            for (platform, download) in downloads {
+                if !download.is_object() {
+                    // The platform is skipped entirely.
+                    report.defaulted.push(format!("downloads.{}", platform));
+                }
+                report.check(
+                    &format!("downloads.{}.", platform),
+                    download,
+                    DOWNLOAD_FIELDS,
+                );
             }
         }
-        return false;
+        if let Err(err) = serde_json::from_value::<Product>(value.clone()) {
+            report.error = Some(err.to_string());
+        }
+        report
+    }
+
+    fn check(&mut self, prefix: &str, value: &Value, fields: &[Field]) {
+        let object = match value.as_object() {
+            Some(object) => object,
+            None => return,
+        };
+        for (field, valid) in fields {
+            match object.get(*field) {
+                None => self.missing.push(format!("{}{}", prefix, field)),
+                Some(value) if !valid(value) => self.defaulted.push(format!("{}{}", prefix, field)),
+                Some(_) => {}
+            }
+        }
+        for key in object.keys() {
+            if !fields.iter().any(|(field, _)| field == key) {
+                self.unexpected.push(format!("{}{}", prefix, key));
+            }
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.unexpected.is_empty()
+            && self.defaulted.is_empty()
+            && self.error.is_none()
+    }
+}
+
+/// The products in a feed that didn't match the shape this model expects.
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub products: Vec<ProductReport>,
+}
+
+impl ValidationReport {
+    /// The products that couldn't be read and were dropped from the feed.
+    pub fn dropped(&self) -> Vec<&ProductReport> {
+        self.products
+            .iter()
+            .filter(|product| product.error.is_some())
+            .collect()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for product in &self.products {
+            writeln!(f, "{}:", product.machine_name)?;
+            if let Some(error) = &product.error {
+                writeln!(f, "  dropped: {}", error)?;
+            }
+            if !product.missing.is_empty() {
+                writeln!(f, "  missing: {}", product.missing.join(", "))?;
+            }
+            if !product.unexpected.is_empty() {
+                writeln!(f, "  unexpected: {}", product.unexpected.join(", "))?;
+            }
+            if !product.defaulted.is_empty() {
+                writeln!(f, "  defaulted: {}", product.defaulted.join(", "))?;
+            }
+        }
+        Ok(())
     }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Feed {
+    #[serde(default)]
     pub all_access: Vec<String>,
+    #[serde(default)]
     pub download_platform_order: Vec<String>,
+    #[serde(default, deserialize_with = "tolerant_products")]
     pub newly_added: Vec<Product>,
     // pub display_item_data: Value,
     pub countdown_timer_options: TimerOptions,
+    #[serde(deserialize_with = "tolerant_products")]
     pub standard_products: Vec<Product>,
     //pub chunks: u8,
     //pub games_per_chunk: u8,
@@ -137,13 +376,49 @@ impl Feed {
     }
 }
 
+/// The id of the element holding the trove's JSON data on the trove page.
+const TROVE_DATA_ID: &str = "webpack-monthly-trove-data";
+
+#[derive(Debug, Fail)]
+pub enum TroveFeedError {
+    #[fail(
+        display = "{} returned a login page; log in to Humble Bundle and save the session in ~/.trove/session.json",
+        url
+    )]
+    LoginRequired { url: String },
+    #[fail(
+        display = "unable to find the #{} element on {}; the page layout may have changed",
+        id, url
+    )]
+    DataNotFound { url: String, id: &'static str },
+    #[fail(display = "the trove data has no chunks value")]
+    MissingChunks,
+    #[fail(display = "the trove data's chunks value is not a count: {}", value)]
+    InvalidChunks { value: String },
+    #[fail(display = "the trove data is not a JSON object")]
+    InvalidData,
+}
+
+/// Whether `doc` looks like the login page Humble redirects to when a session is needed.
+fn is_login_page(doc: &Document) -> bool {
+    let login_form = doc.find(Name("form")).any(|form| {
+        form.attr("action")
+            .map(|action| action.contains("login"))
+            .unwrap_or(false)
+    });
+    let login_title = doc
+        .find(Name("title"))
+        .any(|title| title.text().to_lowercase().contains("log in"));
+    login_form || login_title
+}
+
 trait TroveCache {
     fn chunk_url(&self, i: usize) -> String;
     fn trove_url(&self) -> &'static str;
     fn feed_doc(&self) -> Result<Value, Error>;
-    fn chunks(&self, root: &Value) -> usize;
+    fn chunks(&self, root: &Value) -> Result<usize, Error>;
     fn get_trove_feed(&self) -> Result<Value, Error>;
-    fn invalidate(&self) -> Result<(), Error>;
+    fn refresh(&self) -> Result<(), Error>;
 }
 
 impl TroveCache for Cache {
@@ -159,31 +434,52 @@ impl TroveCache for Cache {
     }
 
     fn feed_doc(&self) -> Result<Value, Error> {
-        let text = self.retrieve(self.trove_url())?;
+        let url = self.trove_url();
+        let text = self.retrieve(url)?;
         let doc = Document::from(str::from_utf8(&text)?);
-        let data = doc
-            .find(Attr("id", "webpack-monthly-trove-data"))
-            .next()
-            .unwrap()
-            .text();
+        let data = match doc.find(Attr("id", TROVE_DATA_ID)).next() {
+            Some(node) => node.text(),
+            None => {
+                // Don't keep serving the unexpected page from the cache.
+                self.invalidate(url)?;
+                if is_login_page(&doc) {
+                    return Err(TroveFeedError::LoginRequired {
+                        url: url.to_string(),
+                    }
+                    .into());
+                }
+                return Err(TroveFeedError::DataNotFound {
+                    url: url.to_string(),
+                    id: TROVE_DATA_ID,
+                }
+                .into());
+            }
+        };
         let root: Value = serde_json::from_str(data.as_str())?;
         Ok(root)
     }
 
-    fn chunks(&self, root: &Value) -> usize {
+    fn chunks(&self, root: &Value) -> Result<usize, Error> {
         debug!("Extracting number of chunks");
-        let chunks: usize = match &root["chunks"] {
-            Value::Number(number) => {
-                number.as_u64().expect("Unable to convert chunks to u64") as usize
+        match &root["chunks"] {
+            Value::Number(number) => match number.as_u64() {
+                Some(chunks) => Ok(chunks as usize),
+                None => Err(TroveFeedError::InvalidChunks {
+                    value: number.to_string(),
+                }
+                .into()),
+            },
+            Value::Null => Err(TroveFeedError::MissingChunks.into()),
+            value => Err(TroveFeedError::InvalidChunks {
+                value: value.to_string(),
             }
-            _ => panic!("Unable to get chunks value!"),
-        };
-        chunks
+            .into()),
+        }
     }
 
     fn get_trove_feed(&self) -> Result<Value, Error> {
         let mut root = self.feed_doc()?;
-        let chunks = self.chunks(&root);
+        let chunks = self.chunks(&root)?;
         debug!("Getting product list");
         let mut products = Vec::new();
         // match root
@@ -199,20 +495,19 @@ impl TroveCache for Cache {
             products.extend(chunk);
         }
         root.as_object_mut()
-            .expect("Unable to get root")
+            .ok_or(TroveFeedError::InvalidData)?
             .insert("standardProducts".to_string(), Value::Array(products));
         Ok(root)
     }
 
-    fn invalidate(&self) -> Result<(), Error> {
-        // This is a bit weird. We retrieve the cached value only to determine
-        // how many chunk urls we need to invalidate. This is needed because we
-        // do not save the extracted chunk value in our exports.
+    fn refresh(&self) -> Result<(), Error> {
+        // The trove page is revalidated first since it determines how many chunk urls
+        // need to be revalidated after it.
+        self.force_retrieve(self.trove_url())?;
         let root = self.feed_doc()?;
-        let chunks = self.chunks(&root);
-        self.invalidate(self.trove_url())?;
+        let chunks = self.chunks(&root)?;
         for i in 0..chunks {
-            self.invalidate(self.chunk_url(i).as_str())?;
+            self.force_retrieve(self.chunk_url(i).as_str())?;
         }
         Ok(())
     }
@@ -233,9 +528,11 @@ impl TroveFeed {
             json,
             feed: serde_json::from_value(root)?,
         };
-        if trove_feed.expired() {
+        if trove_feed.expired() && trove_feed.cache.is_offline() {
+            warn!("Feed is expired; not refreshing while offline.");
+        } else if trove_feed.expired() {
             eprintln!("Refreshing expired cache.");
-            TroveCache::invalidate(&trove_feed.cache)?;
+            TroveCache::refresh(&trove_feed.cache)?;
             return TroveFeed::new(trove_feed.cache, dir);
         }
         let mut products: Vec<String> = Vec::new();
@@ -256,17 +553,43 @@ impl TroveFeed {
             }
         });
         trove_feed.feed.alphabetically();
+        let report = trove_feed.validate()?;
+        if !report.products.is_empty() {
+            warn!(
+                "{} products did not match the expected feed format; {} were dropped.",
+                report.products.len(),
+                report.dropped().len()
+            );
+        }
         trove_feed.save(&dir.join("trove_feed.json"))?;
-        trove_feed.backup(dir)?;
+        // Backups are dated the day they are taken, so only back up a feed fetched today.
+        let fetched_today = trove_feed
+            .cache
+            .fetched_at(trove_feed.cache.trove_url())
+            .map(|fetched_at| fetched_at.date() == Utc::now().date())
+            .unwrap_or(false);
+        if trove_feed.cache.is_offline() || !fetched_today {
+            info!("Feed was not fetched today; not creating a backup.");
+        } else {
+            trove_feed.backup(dir)?;
+        }
         Ok(trove_feed)
     }
 
+    /// Whether the trove has been updated since the feed was generated. A feed whose
+    /// `nextAdditionTime` can't be parsed is never considered expired, since refreshing it
+    /// would not help.
     pub fn expired(&self) -> bool {
-        let expiration = NaiveDateTime::parse_from_str(
+        let expiration = match NaiveDateTime::parse_from_str(
             &self.feed.countdown_timer_options.next_addition_time,
             "%Y-%m-%dT%H:%M:%S%.f",
-        )
-        .expect("Error parsing nextAdditionTime");
+        ) {
+            Ok(expiration) => expiration,
+            Err(e) => {
+                warn!("Error parsing nextAdditionTime: {}", e);
+                return false;
+            }
+        };
         debug!("Expiration: {}", expiration);
         if Utc::now().timestamp() > expiration.timestamp() {
             return true;
@@ -274,42 +597,70 @@ impl TroveFeed {
         return false;
     }
 
-    pub fn cache_images(&self) {
-        self.feed.images().iter().for_each(|image| {
-            if let Err(err) = self.cache.retrieve(image) {
-                warn!("{}", err);
+    /// The day the feed was generated, as `YYYY-MM-DD`. Falls back to today if the feed's
+    /// `currentTime` can't be parsed.
+    pub fn date(&self) -> String {
+        match NaiveDateTime::parse_from_str(
+            &self.feed.countdown_timer_options.current_time,
+            "%Y-%m-%dT%H:%M:%S%.f",
+        ) {
+            Ok(current_time) => current_time.format(DATE_FORMAT).to_string(),
+            Err(e) => {
+                warn!("Error parsing currentTime: {}", e);
+                Utc::now().format(DATE_FORMAT).to_string()
             }
-        });
-        self.cache_screenshots();
-        self.cache_thumbnails();
+        }
     }
 
-    pub fn cache_thumbnails(&self) {
-        (&self.feed.standard_products)
-            .iter()
-            .flat_map(|p| &p.carousel_content.thumbnail)
-            .for_each(|url| {
-                if let Err(err) = self.cache.retrieve(url.as_str()) {
-                    warn!("{}", err);
-                }
-            });
+    pub fn cache_images(&self, concurrency: usize) {
+        self.prefetch(self.feed.images(), concurrency);
+        self.cache_screenshots(concurrency);
+        self.cache_thumbnails(concurrency);
     }
 
-    pub fn cache_screenshots(&self) {
-        (&self.feed.standard_products)
-            .iter()
-            .flat_map(|p| &p.carousel_content.screenshot)
-            .for_each(|url| {
-                if let Err(err) = self.cache.retrieve(url.as_str()) {
-                    warn!("{}", err);
-                }
-            });
+    pub fn cache_thumbnails(&self, concurrency: usize) {
+        self.prefetch(
+            (&self.feed.standard_products)
+                .iter()
+                .flat_map(|p| &p.carousel_content.thumbnail),
+            concurrency,
+        );
     }
 
+    pub fn cache_screenshots(&self, concurrency: usize) {
+        self.prefetch(
+            (&self.feed.standard_products)
+                .iter()
+                .flat_map(|p| &p.carousel_content.screenshot),
+            concurrency,
+        );
+    }
+
+    fn prefetch<I, S>(&self, urls: I, concurrency: usize)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let report = self.cache.prefetch_all(urls, concurrency);
+        for (_, err) in &report.failed {
+            warn!("{}", err);
+        }
+        info!(
+            "Cached {} urls; {} failed.",
+            report.succeeded.len(),
+            report.failed.len()
+        );
+    }
+
+    /// Loads a feed saved by `save` or `backup`. Gzip compressed backups, named `*.gz`,
+    /// are decompressed first.
     pub fn load(cache: Cache, path: &PathBuf) -> Result<TroveFeed, Error> {
-        let mut json = String::new();
-        let mut file = File::open(path)?;
-        file.read_to_string(&mut json)?;
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        if path.extension().map(|ext| ext == "gz").unwrap_or(false) {
+            bytes = gunzip(&bytes)?;
+        }
+        let json = String::from_utf8(bytes)?;
         let mut feed: Feed = serde_json::from_str(&json)?;
         let mut products: Vec<String> = Vec::new();
         feed.standard_products.retain(|p| {
@@ -338,34 +689,73 @@ impl TroveFeed {
     }
 
     pub fn backup(&self, dir: &PathBuf) -> Result<(), Error> {
-        let filename = Utc::now().format("trove_feed-%Y-%m-%d.json").to_string();
+        // Compress older backups first so none of them can replace today's.
+        TroveFeed::compress_backups(dir)?;
+        let filename = Utc::now().format("trove_feed-%Y-%m-%d.json.gz").to_string();
         info!("Creating backup: {}.", &filename);
-        self.save(&dir.join(filename))?;
+        let mut file = File::create(dir.join(filename))?;
+        file.write_all(&gzip(self.json.as_bytes())?)?;
         Ok(())
     }
 
-    pub fn diff(&self, older: TroveFeed) {
-        let mut new_names = Vec::new();
-        for product in &self.feed.standard_products {
-            if !older.feed.standard_products.contains(&product.human_name) {
-                new_names.push(product.human_name.clone());
+    /// Compresses backups made before they were written compressed, removing the
+    /// uncompressed copies. Backups that already have a compressed copy are left alone.
+    /// Returns the compressed backups that were created.
+    pub fn compress_backups(dir: &PathBuf) -> Result<Vec<PathBuf>, Error> {
+        let mut compressed = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if !name.starts_with("trove_feed-") || !name.ends_with(".json") {
+                continue;
             }
+            let target = dir.join(format!("{}.gz", &name));
+            if target.exists() {
+                warn!("Not compressing {}; {}.gz already exists.", &name, &name);
+                continue;
+            }
+            info!("Compressing backup: {}.", &name);
+            let mut file = File::create(&target)?;
+            file.write_all(&gzip(&fs::read(&path)?)?)?;
+            fs::remove_file(&path)?;
+            compressed.push(target);
         }
+        Ok(compressed)
+    }
 
-        let mut old_names = Vec::new();
-        for product in older.feed.standard_products {
-            if !&self.feed.standard_products.contains(&product.human_name) {
-                old_names.push(product.human_name.clone());
+    /// Compares this feed with an older one.
+    pub fn diff(&self, older: &TroveFeed) -> FeedDiff {
+        FeedDiff::new(&older.feed.standard_products, &self.feed.standard_products)
+    }
+
+    /// Checks every product in the feed's JSON against the fields this model expects and
+    /// reports the ones that don't match.
+    pub fn validate(&self) -> Result<ValidationReport, Error> {
+        let root: Value = serde_json::from_str(&self.json)?;
+        let mut report = ValidationReport::default();
+        for key in &["standardProducts", "newlyAdded"] {
+            if let Some(products) = root[*key].as_array() {
+                report.products.extend(
+                    products
+                        .iter()
+                        .map(ProductReport::new)
+                        .filter(|product| !product.is_clean()),
+                );
             }
         }
+        Ok(report)
+    }
 
-        println!("Added titles:");
-        println!("-------------");
-        new_names.iter().for_each(|name| println!("{}", name));
-        println!("");
-        println!("Deleted titles:");
-        println!("---------------");
-        old_names.iter().for_each(|name| println!("{}", name));
+    /// Whether every product in the feed could be read. A feed that dropped products only
+    /// lists some of what is in the trove.
+    pub fn is_complete(&self) -> bool {
+        match self.validate() {
+            Ok(report) => report.dropped().is_empty(),
+            Err(_) => false,
+        }
     }
 
     pub fn products(&self) -> &Vec<Product> {
@@ -1,8 +1,35 @@
-use std::fs::File;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::Digest;
+use std::fs::{self, File};
 use std::io::{Error, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use url::{ParseError, Url};
 
+pub fn sha256<T: AsRef<[u8]>>(data: T) -> String {
+    let mut hasher = sha2::Sha256::new();
+    hasher.input(data.as_ref());
+    hex::encode(&hasher.result())
+}
+
+/// Hashes the file at `path` without reading it into memory all at once.
+pub fn md5_file(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = md5::Md5::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.input(&buffer[..read]);
+    }
+    Ok(hex::encode(&hasher.result()))
+}
+
 pub fn create_file(name: PathBuf, contents: &str) -> Result<(), Error> {
     println!("Creating file: {}", name.display());
     File::create(name)?.write(contents.as_bytes())?;
@@ -21,6 +48,56 @@ pub fn read_file(name: PathBuf) -> Result<Vec<u8>, Error> {
     Ok(buffer)
 }
 
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `contents` to a temporary file next to `path` and renames it into place, so
+/// readers see either the old or the new contents and never a partial write.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(
+        "{}.{}-{}.tmp",
+        name,
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+    fs::rename(&temp, path)
+}
+
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut buffer = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Formats a number of bytes for display, e.g. `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, units[0]),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
+
 pub fn url_path(url: &str) -> Result<String, ParseError> {
     Ok(Url::parse(url)?.path().to_string().clone())
 }
@@ -32,6 +109,22 @@ pub fn url_path_ext(url: String) -> Option<String> {
     }
 }
 
+/// Maps a `Content-Type` header value to the file extension usually used for it.
+pub fn content_type_ext(content_type: &str) -> Option<String> {
+    let mime = content_type.split(";").next()?.trim().to_lowercase();
+    let ext = match mime.as_str() {
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "application/json" => "json",
+        "text/html" => "html",
+        _ => return None,
+    };
+    Some(ext.to_string())
+}
+
 pub fn extension(name: &str) -> Option<String> {
     let mut iter = name.rsplit(".");
     Some(iter.next()?.split("?").next()?.to_string())
@@ -45,3 +138,24 @@ pub fn extension(name: &str) -> Option<String> {
         None => None,
     }*/
 }
+
+/// Matches `text` against `pattern`, where `*` matches any run of characters.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    if text.len() < first.len() + last.len() || !text.starts_with(first) || !text.ends_with(last) {
+        return false;
+    }
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
@@ -0,0 +1,282 @@
+mod common;
+
+use chrono::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::{thread, time};
+use trove::{Cache, CacheError, Fetcher, Response, Session};
+
+#[test]
+fn policies_match_urls_by_glob() {
+    let dir = common::temp_dir("policies");
+    let cache = Cache::new(dir.join("cache"))
+        .with_max_age(Duration::hours(1))
+        .with_policy("https://hb.imgix.net/*", Duration::days(30))
+        .with_policy("*.json", Duration::minutes(5))
+        .with_policy("https://*/trove/*", Duration::days(1));
+
+    let max_age = |url: &str| cache.max_age(url).unwrap();
+    assert_eq!(max_age("https://hb.imgix.net/a.png"), Duration::days(30));
+    // Policies are checked in order, so the image host wins over the extension.
+    assert_eq!(max_age("https://hb.imgix.net/a.json"), Duration::days(30));
+    assert_eq!(
+        max_age("https://example.com/chunk.json"),
+        Duration::minutes(5)
+    );
+    assert_eq!(
+        max_age("https://www.humblebundle.com/trove/x"),
+        Duration::days(1)
+    );
+    // Anything else falls back to the default.
+    assert_eq!(max_age("https://hb.imgix.net"), Duration::hours(1));
+    assert_eq!(
+        max_age("https://example.com/chunk.jsonp"),
+        Duration::hours(1)
+    );
+    assert_eq!(max_age("https://example.com/trove"), Duration::hours(1));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn policy_patterns_match_wildcards_in_order() {
+    let dir = common::temp_dir("patterns");
+    let matches = |pattern: &str, url: &str| {
+        Cache::new(dir.join("cache"))
+            .with_policy(pattern, Duration::days(1))
+            .max_age(url)
+            .is_some()
+    };
+    assert!(matches("a*b*c", "abc"));
+    assert!(matches("a*b*c", "aXbYc"));
+    assert!(matches("exact", "exact"));
+    assert!(!matches("exact", "exactly"));
+    assert!(!matches("a*a", "a"));
+    assert!(!matches("a*b*c", "acb"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Answers each request with the next of a list of responses and remembers the request
+/// headers it was sent.
+struct Scripted {
+    responses: Mutex<VecDeque<Response>>,
+    requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+}
+
+impl Scripted {
+    fn new(responses: Vec<(u16, &[(&str, &str)], &str)>) -> Scripted {
+        Scripted {
+            responses: Mutex::new(
+                responses
+                    .into_iter()
+                    .map(|(status, headers, body)| Response {
+                        status,
+                        headers: headers
+                            .iter()
+                            .map(|(name, value)| (name.to_string(), value.to_string()))
+                            .collect(),
+                        body: body.as_bytes().to_vec(),
+                    })
+                    .collect(),
+            ),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl Fetcher for Scripted {
+    fn fetch(&self, url: &str, headers: &HashMap<String, String>) -> Result<Response, CacheError> {
+        self.requests.lock().unwrap().push(headers.clone());
+        match self.responses.lock().unwrap().pop_front() {
+            Some(response) => Ok(response),
+            None => panic!("unexpected request for {}", url),
+        }
+    }
+}
+
+const URL: &str = "https://www.humblebundle.com/api/v1/trove/chunk?index=0";
+
+#[test]
+fn refetching_keeps_replaced_versions() {
+    let dir = common::temp_dir("versions");
+    let cache = Cache::new(dir.join("cache")).with_fetcher(Scripted::new(vec![
+        (200, &[], "v1"),
+        (200, &[], "v1"),
+        (200, &[], "v2"),
+    ]));
+    assert_eq!(cache.force_retrieve(URL).unwrap(), b"v1");
+    assert_eq!(cache.versions(URL).unwrap().len(), 1);
+
+    // Versions are named by the second they were fetched in.
+    thread::sleep(time::Duration::from_millis(1100));
+    assert_eq!(cache.force_retrieve(URL).unwrap(), b"v1");
+    assert_eq!(cache.versions(URL).unwrap().len(), 1);
+
+    thread::sleep(time::Duration::from_millis(1100));
+    assert_eq!(cache.force_retrieve(URL).unwrap(), b"v2");
+    let versions = cache.versions(URL).unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(cache.retrieve_version(URL, &versions[0]).unwrap(), b"v1");
+    assert_eq!(cache.retrieve_version(URL, &versions[1]).unwrap(), b"v2");
+    let before_v2 = versions[1] - Duration::milliseconds(1);
+    assert_eq!(
+        cache.retrieve_as_of(URL, &before_v2).unwrap().unwrap(),
+        b"v1"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn not_modified_only_refreshes_the_entry() {
+    let dir = common::temp_dir("not-modified");
+    let fetcher = Scripted::new(vec![
+        (
+            200,
+            &[
+                ("etag", "\"abc\""),
+                ("last-modified", "Fri, 01 May 2020 17:00:00 GMT"),
+            ],
+            "v1",
+        ),
+        (304, &[], ""),
+        (304, &[], ""),
+    ]);
+    let requests = fetcher.requests.clone();
+    let cache = Cache::new(dir.join("cache")).with_fetcher(fetcher);
+    assert_eq!(cache.force_retrieve(URL).unwrap(), b"v1");
+    let fetched_at = cache.fetched_at(URL).unwrap();
+
+    thread::sleep(time::Duration::from_millis(10));
+    assert_eq!(cache.force_retrieve(URL).unwrap(), b"v1");
+    let requests = requests.lock().unwrap().clone();
+    assert!(requests[0].is_empty());
+    assert_eq!(requests[1]["if-none-match"], "\"abc\"");
+    assert_eq!(
+        requests[1]["if-modified-since"],
+        "Fri, 01 May 2020 17:00:00 GMT"
+    );
+    let metadata = cache.metadata(URL).unwrap();
+    assert!(metadata.fetched_at > fetched_at);
+    assert_eq!(metadata.headers["etag"], "\"abc\"");
+    assert_eq!(cache.versions(URL).unwrap().len(), 1);
+
+    // Without a cached copy there is nothing a 304 could refer to.
+    match cache.force_retrieve(&format!("{}1", URL)) {
+        Err(CacheError::Status { code: 304, .. }) => {}
+        other => panic!("expected a 304 error, got {:?}", other.map(|_| ())),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn evicts_least_recently_used_entries() {
+    let dir = common::temp_dir("evict");
+    let cache = Cache::new(dir.join("cache"))
+        .with_max_size(10)
+        .with_fetcher(Scripted::new(vec![
+            (200, &[], "aaaa"),
+            (200, &[], "bbbb"),
+            (200, &[], "cccc"),
+            (200, &[], "dddd"),
+        ]));
+    let url = |name: &str| format!("https://hb.imgix.net/{}.png", name);
+    let cached = |cache: &Cache| {
+        let mut urls: Vec<String> = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.url)
+            .collect();
+        urls.sort();
+        urls
+    };
+    for name in &["a", "b", "c"] {
+        cache.retrieve(&url(name)).unwrap();
+        thread::sleep(time::Duration::from_millis(5));
+    }
+    assert_eq!(cached(&cache), vec![url("b"), url("c")]);
+
+    // Using an entry keeps it around.
+    assert_eq!(cache.retrieve(&url("b")).unwrap(), b"bbbb");
+    thread::sleep(time::Duration::from_millis(5));
+    cache.retrieve(&url("d")).unwrap();
+    assert_eq!(cached(&cache), vec![url("b"), url("d")]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn prefetch_evicts_once_everything_is_fetched() {
+    let dir = common::temp_dir("prefetch-evict");
+    let cache = Cache::new(dir.join("cache"))
+        .with_max_size(10)
+        .with_fetcher(Scripted::new(vec![(200, &[], "xxxx"); 4]));
+    let urls: Vec<String> = (0..4)
+        .map(|i| format!("https://hb.imgix.net/{}.png", i))
+        .collect();
+    let report = cache.prefetch_all(&urls, 2);
+    assert_eq!(report.succeeded.len(), 4);
+    assert!(report.failed.is_empty());
+    assert_eq!(cache.entries().unwrap().len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn export_and_import_keep_the_newest_copy() {
+    let dir = common::temp_dir("export");
+    let one = "https://hb.imgix.net/1.png";
+    let two = "https://hb.imgix.net/2.png";
+    let older = Cache::new(dir.join("older")).with_fetcher(Scripted::new(vec![(200, &[], "old")]));
+    older.retrieve(one).unwrap();
+    // Versions are named by the second they were fetched in.
+    thread::sleep(time::Duration::from_millis(1100));
+    let exported = Cache::new(dir.join("exported"))
+        .with_compression(true)
+        .with_fetcher(Scripted::new(vec![(200, &[], "one"), (200, &[], "two")]));
+    exported.retrieve(one).unwrap();
+    exported.retrieve(two).unwrap();
+    thread::sleep(time::Duration::from_millis(10));
+    let newer = Cache::new(dir.join("newer")).with_fetcher(Scripted::new(vec![(200, &[], "TWO")]));
+    newer.retrieve(two).unwrap();
+
+    let archive = dir.join("cache.tar");
+    assert_eq!(exported.export(&archive).unwrap(), 2);
+
+    let report = newer.import(&archive).unwrap();
+    assert_eq!(report.imported, vec![one.to_string()]);
+    assert_eq!(report.skipped, vec![two.to_string()]);
+    assert_eq!(newer.retrieve(one).unwrap(), b"one");
+    assert_eq!(newer.retrieve(two).unwrap(), b"TWO");
+    assert!(newer.verify(one).unwrap());
+
+    // The copy that was replaced goes into the history.
+    let report = older.import(&archive).unwrap();
+    assert_eq!(report.imported.len(), 2);
+    assert_eq!(older.retrieve(one).unwrap(), b"one");
+    let versions = older.versions(one).unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(older.retrieve_version(one, &versions[0]).unwrap(), b"old");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn session_credentials_are_neither_leaked_nor_cached() {
+    let dir = common::temp_dir("session");
+    let session: Session =
+        serde_json::from_str(r#"{"cookies": {"_simpleauth_sess": "secret"}}"#).unwrap();
+    let fetcher = Scripted::new(vec![
+        (200, &[("set-cookie", "_simpleauth_sess=new")], "trove"),
+        (200, &[], "image"),
+    ]);
+    let requests = fetcher.requests.clone();
+    let cache = Cache::new(dir.join("cache"))
+        .with_session(session)
+        .with_fetcher(fetcher);
+    cache.retrieve(URL).unwrap();
+    cache.retrieve("https://hb.imgix.net/a.png").unwrap();
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests[0]["cookie"], "_simpleauth_sess=secret");
+    assert!(!requests[1].contains_key("cookie"));
+    let metadata = cache.metadata(URL).unwrap();
+    assert!(!metadata.headers.contains_key("set-cookie"));
+    assert!(!metadata.headers.contains_key("cookie"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
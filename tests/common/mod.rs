@@ -0,0 +1,78 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use trove::{Cache, Trove, TroveFeed};
+
+pub fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/trove")
+}
+
+/// Creates an empty directory for a test under the system temp directory.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("trove-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn new_trove(dir: &PathBuf) -> Trove {
+    let root = dir.join("root");
+    let downloads = dir.join("downloads");
+    fs::create_dir_all(&root).unwrap();
+    fs::create_dir_all(&downloads).unwrap();
+    Trove::new(&root, &downloads).unwrap()
+}
+
+pub fn product(
+    machine_name: &str,
+    human_name: &str,
+    file: &str,
+    md5: &str,
+    file_size: u64,
+) -> Value {
+    json!({
+        "machine_name": machine_name,
+        "human-name": human_name,
+        "image": format!("https://hb.imgix.net/{}.png", machine_name),
+        "downloads": {
+            "windows": {
+                "machine_name": format!("{}_windows", machine_name),
+                "url": {"web": format!("https://dl.humble.com/{}", file)},
+                "file_size": file_size,
+                "md5": md5
+            }
+        }
+    })
+}
+
+/// The feed json served by the trove page, holding `products`.
+pub fn feed_json(current_time: &str, products: Vec<Value>) -> Value {
+    json!({
+        "countdownTimerOptions": {
+            "currentTime|datetime": current_time,
+            "nextAdditionTime|datetime": "2099-06-05T17:00:00.000000"
+        },
+        "newlyAdded": [],
+        "standardProducts": products
+    })
+}
+
+/// Writes a feed with `products` to `dir` and loads it.
+pub fn load_feed(dir: &PathBuf, name: &str, current_time: &str, products: Vec<Value>) -> TroveFeed {
+    let path = dir.join(name);
+    let feed = feed_json(current_time, products);
+    fs::write(&path, serde_json::to_vec(&feed).unwrap()).unwrap();
+    TroveFeed::load(Cache::new(dir.join("cache")).with_offline(true), &path).unwrap()
+}
+
+/// Finds the game called `machine_name` in `trove`.
+pub fn game<'a>(trove: &'a Trove, machine_name: &str) -> &'a trove::TroveGame {
+    trove
+        .games
+        .iter()
+        .find(|g| g.machine_name == machine_name)
+        .unwrap()
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use trove::{DownloadError, Downloader};
+
+const INSTALLER: &str = "hello world";
+
+/// Serves `INSTALLER` on a local port. `respond` is given the start of the requested range,
+/// if any, and returns the status line and headers to answer with, and the body. Returns
+/// the url to request and the ranges that were asked for.
+fn serve<F>(respond: F) -> (String, Arc<Mutex<Vec<Option<u64>>>>)
+where
+    F: Fn(Option<u64>) -> (String, String) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/installer.exe", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let range = String::from_utf8_lossy(&request)
+                .lines()
+                .find(|line| line.to_lowercase().starts_with("range: bytes="))
+                .and_then(|line| line[13..].trim_end_matches('-').parse().ok());
+            seen.lock().unwrap().push(range);
+            let (head, body) = respond(range);
+            let response = format!(
+                "{}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                head,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    (url, requests)
+}
+
+/// Answers like a server that supports ranges.
+fn with_ranges(range: Option<u64>) -> (String, String) {
+    match range {
+        Some(start) if start as usize >= INSTALLER.len() => (
+            format!(
+                "HTTP/1.1 416 Range Not Satisfiable\r\ncontent-range: bytes */{}",
+                INSTALLER.len()
+            ),
+            String::new(),
+        ),
+        Some(start) => (
+            format!(
+                "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes {}-{}/{}",
+                start,
+                INSTALLER.len() - 1,
+                INSTALLER.len()
+            ),
+            INSTALLER[start as usize..].to_string(),
+        ),
+        None => ("HTTP/1.1 200 OK".to_string(), INSTALLER.to_string()),
+    }
+}
+
+/// Downloads from `url` into `dir`, starting from a `.part` file holding `part`.
+fn download(dir: &std::path::PathBuf, url: &str, part: &str) -> Result<u64, DownloadError> {
+    let dest = dir.join("installer.exe");
+    let _ = fs::remove_file(&dest);
+    fs::write(Downloader::part_path(&dest), part).unwrap();
+    Downloader::new().download(url, &dest, |_| {})
+}
+
+#[test]
+fn resumes_a_partial_download() {
+    let dir = common::temp_dir("resume");
+    let (url, requests) = serve(with_ranges);
+    assert_eq!(download(&dir, &url, "hello ").unwrap(), 11);
+    assert_eq!(
+        fs::read_to_string(dir.join("installer.exe")).unwrap(),
+        INSTALLER
+    );
+    assert!(!Downloader::part_path(&dir.join("installer.exe")).exists());
+    assert_eq!(*requests.lock().unwrap(), vec![Some(6)]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn finishes_a_download_that_was_complete() {
+    let dir = common::temp_dir("complete");
+    let (url, requests) = serve(with_ranges);
+    assert_eq!(download(&dir, &url, INSTALLER).unwrap(), 11);
+    assert_eq!(
+        fs::read_to_string(dir.join("installer.exe")).unwrap(),
+        INSTALLER
+    );
+    assert_eq!(*requests.lock().unwrap(), vec![Some(11)]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn starts_over_when_the_part_file_is_too_long() {
+    let dir = common::temp_dir("too-long");
+    let (url, requests) = serve(with_ranges);
+    // Left behind by an older version of the installer.
+    assert_eq!(download(&dir, &url, "hello world, again").unwrap(), 11);
+    assert_eq!(
+        fs::read_to_string(dir.join("installer.exe")).unwrap(),
+        INSTALLER
+    );
+    assert_eq!(*requests.lock().unwrap(), vec![Some(18), None]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn starts_over_when_the_wrong_range_is_sent() {
+    let dir = common::temp_dir("wrong-range");
+    let (url, requests) = serve(|range| match range {
+        Some(_) => with_ranges(Some(0)),
+        None => with_ranges(None),
+    });
+    assert_eq!(download(&dir, &url, "hello ").unwrap(), 11);
+    assert_eq!(
+        fs::read_to_string(dir.join("installer.exe")).unwrap(),
+        INSTALLER
+    );
+    assert_eq!(*requests.lock().unwrap(), vec![Some(6), None]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn starts_over_when_ranges_are_not_supported() {
+    let dir = common::temp_dir("no-ranges");
+    let (url, _) = serve(|_| with_ranges(None));
+    assert_eq!(download(&dir, &url, "hello ").unwrap(), 11);
+    assert_eq!(
+        fs::read_to_string(dir.join("installer.exe")).unwrap(),
+        INSTALLER
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
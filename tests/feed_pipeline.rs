@@ -0,0 +1,27 @@
+mod common;
+
+use common::{fixtures, temp_dir};
+use std::fs;
+use trove::{Backup, Cache, FixtureFetcher, TroveFeed};
+
+#[test]
+fn builds_feed_from_fixtures() {
+    let dir = temp_dir("fixtures");
+    let cache =
+        Cache::new(dir.join("cache")).with_fetcher(FixtureFetcher::new(fixtures()).unwrap());
+    let feed = TroveFeed::new(cache, &dir).unwrap();
+
+    let names: Vec<&str> = feed
+        .products()
+        .iter()
+        .map(|p| p.machine_name.as_str())
+        .collect();
+    assert_eq!(names, vec!["alpha_trove", "beta_trove", "delta_trove"]);
+    assert_eq!(feed.date(), "2020-05-01");
+
+    assert!(dir.join("trove_feed.json").exists());
+    assert_eq!(Backup::find_all(&dir).unwrap().len(), 1);
+    // The trove page and its one chunk are cached for the next run.
+    assert_eq!(Cache::new(dir.join("cache")).entries().unwrap().len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
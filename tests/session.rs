@@ -0,0 +1,51 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use trove::Session;
+
+fn session() -> Session {
+    serde_json::from_str(
+        r#"{"cookies": {"_simpleauth_sess": "secret"}, "headers": {"User-Agent": "trove"}}"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn applies_only_to_its_hosts_over_https() {
+    let session = session();
+    assert_eq!(session.hosts, vec!["humblebundle.com".to_string()]);
+    assert!(session.applies_to("https://humblebundle.com/"));
+    assert!(session.applies_to("https://www.humblebundle.com/api/v1/trove/chunk?index=0"));
+    assert!(!session.applies_to("http://www.humblebundle.com/"));
+    assert!(!session.applies_to("https://nothumblebundle.com/"));
+    assert!(!session.applies_to("https://humblebundle.com.example.com/"));
+    assert!(!session.applies_to("https://hb.imgix.net/a.png"));
+    assert!(!session.applies_to("not a url"));
+
+    let mut request = HashMap::new();
+    session.authorize("https://hb.imgix.net/a.png", &mut request);
+    assert!(request.is_empty());
+    session.authorize("https://www.humblebundle.com/", &mut request);
+    assert_eq!(request["cookie"], "_simpleauth_sess=secret");
+    assert_eq!(request["user-agent"], "trove");
+}
+
+#[test]
+fn reads_cookies_for_its_hosts_from_a_cookie_jar() {
+    let dir = common::temp_dir("cookies");
+    let jar = dir.join("cookies.txt");
+    fs::write(
+        &jar,
+        "# Netscape HTTP Cookie File\n\
+         #HttpOnly_.humblebundle.com\tTRUE\t/\tTRUE\t0\t_simpleauth_sess\tsecret\n\
+         www.humblebundle.com\tFALSE\t/\tTRUE\t0\tcsrf_cookie\ttoken\n\
+         .example.com\tTRUE\t/\tTRUE\t0\ttracker\tnope\n",
+    )
+    .unwrap();
+    let session = Session::from_cookie_jar(&jar).unwrap();
+    assert_eq!(session.cookies.len(), 2);
+    assert_eq!(session.cookies["_simpleauth_sess"], "secret");
+    assert_eq!(session.cookies["csrf_cookie"], "token");
+    fs::remove_dir_all(&dir).unwrap();
+}
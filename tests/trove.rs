@@ -0,0 +1,344 @@
+mod common;
+
+use common::{feed_json, game, load_feed, new_trove, product, temp_dir};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use trove::{InstallerStatus, Timeline, Trove};
+
+const V1_MD5: &str = "6654c734ccab8f440ff0825eb443dc7f";
+const V2_MD5: &str = "1b267619c4812cc46ee281747884ca50";
+
+#[test]
+fn outdated_lists_installers_replaced_under_the_same_name() {
+    let dir = temp_dir("outdated");
+    let feed = |name: &str, current_time: &str, md5: &str| {
+        load_feed(
+            &dir,
+            name,
+            current_time,
+            vec![product("alpha_trove", "Alpha", "alpha.exe", md5, 2)],
+        )
+    };
+    let mut trove = new_trove(&dir);
+    trove.add_games(feed("older.json", "2020-05-01T17:00:00.000000", V1_MD5));
+    trove.add_games(feed("newer.json", "2020-06-01T17:00:00.000000", V2_MD5));
+    // Replaying feeds that were already merged changes nothing.
+    trove.add_games(feed("newer.json", "2020-06-01T17:00:00.000000", V2_MD5));
+    trove.add_games(feed("older.json", "2020-05-01T17:00:00.000000", V1_MD5));
+
+    let alpha = game(&trove, "alpha_trove");
+    assert_eq!(alpha.installers["windows"].md5, V2_MD5);
+    let previous: Vec<&str> = alpha.previous_installers["windows"]
+        .iter()
+        .map(|installer| installer.md5.as_str())
+        .collect();
+    assert_eq!(previous, vec![V1_MD5]);
+    assert_eq!(alpha.last_seen_on, "2020-06-01");
+
+    let root = dir.join("root");
+    fs::write(root.join("alpha.exe"), "v1").unwrap();
+    let outdated: Vec<PathBuf> = trove
+        .outdated()
+        .unwrap()
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect();
+    assert_eq!(outdated, vec![root.join("alpha.exe")]);
+
+    fs::remove_file(root.join("alpha.exe")).unwrap();
+    assert!(trove.outdated().unwrap().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn history_replays_backups_by_feed_date() {
+    let dir = temp_dir("history");
+    let backups = dir.join("backups");
+    fs::create_dir_all(&backups).unwrap();
+    let backup = |name: &str, current_time: &str, products: Vec<Value>| {
+        let feed = feed_json(current_time, products);
+        fs::write(backups.join(name), feed.to_string()).unwrap();
+    };
+    let alpha = || product("alpha_trove", "Alpha", "alpha.exe", "a", 1);
+    let beta = || product("beta_trove", "Beta", "beta.exe", "b", 1);
+    let gamma = || product("gamma_trove", "Gamma", "gamma.exe", "c", 1);
+    // Backups are named by the day they were taken, the day after the feeds were made.
+    backup(
+        "trove_feed-2020-05-02.json",
+        "2020-05-01T17:00:00.000000",
+        vec![alpha(), beta()],
+    );
+    backup(
+        "trove_feed-2020-06-02.json",
+        "2020-06-01T17:00:00.000000",
+        vec![alpha()],
+    );
+
+    let mut trove = new_trove(&dir);
+    trove.add_games(load_feed(
+        &dir,
+        "june.json",
+        "2020-06-15T17:00:00.000000",
+        vec![alpha(), gamma()],
+    ));
+    trove.add_games(load_feed(
+        &dir,
+        "july.json",
+        "2020-07-01T17:00:00.000000",
+        vec![gamma()],
+    ));
+    let timeline = Timeline::replay(&backups, &mut trove).unwrap();
+
+    let dates: Vec<String> = timeline
+        .snapshots
+        .iter()
+        .map(|snapshot| snapshot.date.to_string())
+        .collect();
+    assert_eq!(dates, vec!["2020-05-01", "2020-06-01"]);
+    let history = &timeline.games["alpha_trove"];
+    assert_eq!(history.first_seen.to_string(), "2020-05-01");
+    assert_eq!(history.last_seen.to_string(), "2020-06-01");
+
+    // What the newer feeds said about alpha isn't undone.
+    let alpha = game(&trove, "alpha_trove");
+    assert_eq!(alpha.last_seen_on, "2020-06-15");
+    assert!(alpha.removed_from_trove);
+    // Beta only ever appeared in a backup.
+    let beta = game(&trove, "beta_trove");
+    assert_eq!(beta.last_seen_on, "2020-05-01");
+    assert!(beta.removed_from_trove);
+    let gamma = game(&trove, "gamma_trove");
+    assert_eq!(gamma.last_seen_on, "2020-07-01");
+    assert!(!gamma.removed_from_trove);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn add_games_tracks_last_seen_and_removals() {
+    let dir = temp_dir("removals");
+    let alpha = || product("alpha_trove", "Alpha", "alpha.exe", "a", 1);
+    let beta = || product("beta_trove", "Beta", "beta.exe", "b", 1);
+    let mut trove = new_trove(&dir);
+    trove.add_games(load_feed(
+        &dir,
+        "may.json",
+        "2020-05-01T17:00:00.000000",
+        vec![alpha(), beta()],
+    ));
+    trove.add_games(load_feed(
+        &dir,
+        "june.json",
+        "2020-06-01T17:00:00.000000",
+        vec![alpha()],
+    ));
+    assert_eq!(game(&trove, "alpha_trove").last_seen_on, "2020-06-01");
+    assert!(!game(&trove, "alpha_trove").removed_from_trove);
+    assert_eq!(game(&trove, "beta_trove").last_seen_on, "2020-05-01");
+    assert!(game(&trove, "beta_trove").removed_from_trove);
+    let removed: Vec<&str> = trove
+        .removed()
+        .iter()
+        .map(|g| g.machine_name.as_str())
+        .collect();
+    assert_eq!(removed, vec!["beta_trove"]);
+
+    // A game that comes back is no longer removed.
+    trove.add_games(load_feed(
+        &dir,
+        "july.json",
+        "2020-07-01T17:00:00.000000",
+        vec![alpha(), beta()],
+    ));
+    assert_eq!(game(&trove, "beta_trove").last_seen_on, "2020-07-01");
+    assert!(trove.removed().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn renamed_games_are_matched_by_machine_name() {
+    let dir = temp_dir("rename");
+    let mut trove = new_trove(&dir);
+    trove.add_games(load_feed(
+        &dir,
+        "older.json",
+        "2020-05-01T17:00:00.000000",
+        vec![product("alpha_trove", "Alpha", "alpha.exe", "a", 1)],
+    ));
+    trove.add_games(load_feed(
+        &dir,
+        "newer.json",
+        "2020-06-01T17:00:00.000000",
+        vec![product("alpha_trove", "Alpha™", "alpha.exe", "a", 1)],
+    ));
+    assert_eq!(trove.games.len(), 1);
+    let alpha = game(&trove, "alpha_trove");
+    assert_eq!(alpha.human_name, "Alpha™");
+    assert_eq!(alpha.aliases, vec!["Alpha".to_string()]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// A trove holding two entries for alpha_trove, as left behind by a rename when games were
+/// matched by human_name.
+fn trove_with_duplicates(dir: &PathBuf, older: (&str, &str), newer: (&str, &str)) -> Trove {
+    let entry = |(human_name, last_seen_on): (&str, &str), downloaded: bool| {
+        json!({
+            "machine_name": "alpha_trove",
+            "human_name": human_name,
+            "description": "",
+            "date_added": 0,
+            "downloaded": downloaded,
+            "installed": false,
+            "executable": "",
+            "download_urls": {},
+            "downloads": {},
+            "logo": null,
+            "image": "",
+            "screenshots": [],
+            "thumbnails": [],
+            "trailer": null,
+            "last_seen_on": last_seen_on,
+            "removed_from_trove": false
+        })
+    };
+    serde_json::from_value(json!({
+        "downloads": dir.join("downloads"),
+        "root": dir.join("root"),
+        "number_downloaded": 0,
+        "total": 0,
+        "games": [entry(older, true), entry(newer, false)]
+    }))
+    .unwrap()
+}
+
+#[test]
+fn merge_duplicates_keeps_the_newest_entry() {
+    let dir = temp_dir("duplicates");
+    let mut trove = trove_with_duplicates(&dir, ("Alpha", "2020-05-01"), ("Alpha™", "2020-06-01"));
+    assert_eq!(trove.merge_duplicates(), 1);
+    assert_eq!(trove.games.len(), 1);
+    let alpha = &trove.games[0];
+    assert_eq!(alpha.human_name, "Alpha™");
+    assert_eq!(alpha.aliases, vec!["Alpha".to_string()]);
+    assert_eq!(alpha.downloaded.get("windows"), Some(&true));
+
+    // Entries saved before last_seen_on was tracked have no date; the later one wins.
+    let mut trove = trove_with_duplicates(&dir, ("Alpha", ""), ("Alpha™", ""));
+    assert_eq!(trove.merge_duplicates(), 1);
+    assert_eq!(trove.games[0].human_name, "Alpha™");
+    assert_eq!(trove.games[0].aliases, vec!["Alpha".to_string()]);
+    assert_eq!(trove.games[0].downloaded.get("windows"), Some(&true));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn status(trove: &Trove, machine_name: &str) -> InstallerStatus {
+    game(trove, machine_name).status["windows"]
+}
+
+#[test]
+fn verify_checks_installers_against_the_feed() {
+    let dir = temp_dir("verify");
+    let older = load_feed(
+        &dir,
+        "older.json",
+        "2020-05-01T17:00:00.000000",
+        vec![product("alpha_trove", "Alpha", "alpha.exe", V1_MD5, 2)],
+    );
+    let newer = load_feed(
+        &dir,
+        "newer.json",
+        "2020-06-01T17:00:00.000000",
+        vec![
+            // Replaced in place with a new version under the same name.
+            product("alpha_trove", "Alpha", "alpha.exe", V2_MD5, 2),
+            product(
+                "beta_trove",
+                "Beta",
+                "beta.exe",
+                "4cc0e29fa4021468fc842674a379d334",
+                14,
+            ),
+            product(
+                "gamma_trove",
+                "Gamma",
+                "gamma.exe",
+                "8eca6b064d1b835e7b8fb31ddeb4394f",
+                15,
+            ),
+            product(
+                "delta_trove",
+                "Delta",
+                "delta.exe",
+                "74a5ffb87a20709a0eeb1f36f9b002fa",
+                15,
+            ),
+            product("epsilon_trove", "Epsilon", "epsilon.exe", "e", 1),
+        ],
+    );
+    let mut trove = new_trove(&dir);
+    trove.add_games(older);
+    trove.add_games(newer);
+    let root = dir.join("root");
+    fs::write(root.join("alpha.exe"), "v1").unwrap();
+    fs::write(root.join("beta.exe"), "beta").unwrap();
+    fs::write(root.join("gamma.exe"), "gamma-installe!").unwrap();
+    fs::write(root.join("delta.exe"), "delta-installer").unwrap();
+
+    trove.verify().unwrap();
+    assert_eq!(status(&trove, "alpha_trove"), InstallerStatus::Outdated);
+    assert_eq!(status(&trove, "beta_trove"), InstallerStatus::Truncated);
+    assert_eq!(status(&trove, "gamma_trove"), InstallerStatus::Corrupt);
+    assert_eq!(status(&trove, "delta_trove"), InstallerStatus::Downloaded);
+    assert_eq!(status(&trove, "epsilon_trove"), InstallerStatus::Missing);
+    assert_eq!(trove.number_downloaded, 1);
+    assert_eq!(trove.hashes.len(), 4);
+
+    // The cached hashes keep a corrupt installer from counting as downloaded.
+    trove.update_download_status();
+    assert_eq!(status(&trove, "gamma_trove"), InstallerStatus::Corrupt);
+    assert_eq!(trove.number_downloaded, 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn verify_falls_back_to_what_the_feed_lists() {
+    let dir = temp_dir("verify-partial");
+    let feed = load_feed(
+        &dir,
+        "feed.json",
+        "2020-06-01T17:00:00.000000",
+        vec![
+            // Neither an md5 nor a size: any copy will do.
+            product("alpha_trove", "Alpha", "alpha.exe", "", 0),
+            // Only a size.
+            product("beta_trove", "Beta", "beta.exe", "", 4),
+            product("gamma_trove", "Gamma", "gamma.exe", "", 20),
+            // Only an md5.
+            product("delta_trove", "Delta", "delta.exe", V2_MD5, 0),
+            product("epsilon_trove", "Epsilon", "epsilon.exe", V2_MD5, 0),
+        ],
+    );
+    let mut trove = new_trove(&dir);
+    trove.add_games(feed);
+    let root = dir.join("root");
+    fs::write(root.join("alpha.exe"), "anything").unwrap();
+    fs::write(root.join("beta.exe"), "beta").unwrap();
+    fs::write(root.join("gamma.exe"), "gamma").unwrap();
+    fs::write(root.join("delta.exe"), "v2").unwrap();
+    fs::write(root.join("epsilon.exe"), "v1").unwrap();
+
+    for verify in &[false, true] {
+        if *verify {
+            trove.verify().unwrap();
+        } else {
+            trove.update_download_status();
+        }
+        assert_eq!(status(&trove, "alpha_trove"), InstallerStatus::Downloaded);
+        assert_eq!(status(&trove, "beta_trove"), InstallerStatus::Downloaded);
+        assert_eq!(status(&trove, "gamma_trove"), InstallerStatus::Truncated);
+        assert_eq!(status(&trove, "delta_trove"), InstallerStatus::Downloaded);
+    }
+    // Only a hash can tell a wrong copy apart when there is no size.
+    assert_eq!(status(&trove, "epsilon_trove"), InstallerStatus::Corrupt);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,266 @@
+mod common;
+
+use chrono::Utc;
+use common::{fixtures, game, load_feed, new_trove, product, temp_dir};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use trove::{Backup, Cache, FixtureFetcher, TroveFeed, TroveFeedError};
+
+#[test]
+fn backup_is_not_replaced_by_an_older_uncompressed_one() {
+    let dir = temp_dir("backup");
+    let feeds = dir.join("feeds");
+    fs::create_dir_all(&feeds).unwrap();
+    let stale = load_feed(
+        &feeds,
+        "stale.json",
+        "2020-04-01T17:00:00.000000",
+        vec![product("alpha_trove", "Alpha", "alpha.exe", "a", 1)],
+    );
+    let fresh = load_feed(
+        &feeds,
+        "fresh.json",
+        "2020-05-01T17:00:00.000000",
+        vec![product("alpha_trove", "Alpha", "alpha.exe", "a", 1)],
+    );
+    // A backup of the same day written before backups were compressed.
+    let today = Utc::now().format("trove_feed-%Y-%m-%d.json").to_string();
+    fs::copy(feeds.join("stale.json"), dir.join(&today)).unwrap();
+    drop(stale);
+
+    fresh.backup(&dir).unwrap();
+    let backups = Backup::find_all(&dir).unwrap();
+    assert_eq!(backups.len(), 1);
+    assert!(backups[0].path.to_string_lossy().ends_with(".json.gz"));
+    let backup = TroveFeed::load(
+        Cache::new(dir.join("cache")).with_offline(true),
+        &backups[0].path,
+    );
+    assert_eq!(backup.unwrap().date(), "2020-05-01");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+const TROVE_URL: &str = "https://www.humblebundle.com/subscription/trove";
+
+/// Loads a feed from a trove page with the given body, and no chunks, into an empty cache.
+fn feed_from_page(dir: &PathBuf, page: &str) -> Result<TroveFeed, failure::Error> {
+    let _ = fs::remove_dir_all(dir.join("cache"));
+    let fixtures = dir.join("fixtures");
+    fs::create_dir_all(&fixtures).unwrap();
+    fs::write(fixtures.join("trove.html"), page).unwrap();
+    let index = json!({ TROVE_URL: {"file": "trove.html"} });
+    fs::write(fixtures.join("index.json"), index.to_string()).unwrap();
+    let cache = Cache::new(dir.join("cache")).with_fetcher(FixtureFetcher::new(&fixtures).unwrap());
+    TroveFeed::new(cache, dir)
+}
+
+fn data_page(data: &str) -> String {
+    format!(
+        "<html><body><script id=\"webpack-monthly-trove-data\">{}</script></body></html>",
+        data
+    )
+}
+
+fn feed_error(dir: &PathBuf, page: &str) -> TroveFeedError {
+    match feed_from_page(dir, page) {
+        Ok(_) => panic!("expected the feed to fail"),
+        Err(err) => match err.downcast::<TroveFeedError>() {
+            Ok(err) => err,
+            Err(err) => panic!("unexpected error: {}", err),
+        },
+    }
+}
+
+#[test]
+fn reports_unexpected_trove_pages() {
+    let dir = temp_dir("feed-errors");
+    let login = "<html><head><title>Log In | Humble Bundle</title></head>\
+                 <body><form action=\"/processlogin\"></form></body></html>";
+    match feed_error(&dir, login) {
+        TroveFeedError::LoginRequired { url } => assert_eq!(url, TROVE_URL),
+        err => panic!("unexpected error: {}", err),
+    }
+    assert!(TroveFeedError::LoginRequired {
+        url: TROVE_URL.to_string()
+    }
+    .to_string()
+    .contains("~/.trove/session.json"));
+    // The unexpected page is not kept in the cache.
+    assert!(Cache::new(dir.join("cache")).metadata(TROVE_URL).is_none());
+
+    match feed_error(&dir, "<html><body>Down for maintenance</body></html>") {
+        TroveFeedError::DataNotFound { url, id } => {
+            assert_eq!(url, TROVE_URL);
+            assert_eq!(id, "webpack-monthly-trove-data");
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    match feed_error(&dir, &data_page("{\"standardProducts\": []}")) {
+        TroveFeedError::MissingChunks => {}
+        err => panic!("unexpected error: {}", err),
+    }
+    for chunks in &["\"1\"", "-1", "1.5"] {
+        match feed_error(&dir, &data_page(&format!("{{\"chunks\": {}}}", chunks))) {
+            TroveFeedError::InvalidChunks { value } => assert_eq!(&value, chunks),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unparseable_times_do_not_panic() {
+    let dir = temp_dir("feed-times");
+    let data = json!({
+        "chunks": 0,
+        "countdownTimerOptions": {
+            "currentTime|datetime": "soon",
+            "nextAdditionTime|datetime": "later"
+        },
+        "standardProducts": []
+    });
+    let feed = feed_from_page(&dir, &data_page(&data.to_string())).unwrap();
+    assert!(!feed.expired());
+    assert_eq!(feed.date(), Utc::now().format("%Y-%m-%d").to_string());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tolerates_nulls_and_unexpected_types() {
+    let dir = temp_dir("tolerant");
+    let cache =
+        Cache::new(dir.join("cache")).with_fetcher(FixtureFetcher::new(fixtures()).unwrap());
+    let feed = TroveFeed::new(cache, &dir).unwrap();
+
+    // Nulls and unexpected types fall back to defaults instead of dropping the product.
+    let beta = &feed.products()[1];
+    assert_eq!(beta.machine_name, "beta_trove");
+    assert_eq!(beta.description_text, "");
+    assert_eq!(beta.popularity, 0);
+    assert!(beta.carousel_content.thumbnail.is_empty());
+    assert_eq!(beta.downloads["linux"].file_size, 0);
+    assert!(beta.extra.contains_key("brand-new-field"));
+
+    // They are reported, as is the product without a name, which is dropped.
+    let report = feed.validate().unwrap();
+    let beta = report
+        .products
+        .iter()
+        .find(|p| p.machine_name == "beta_trove")
+        .unwrap();
+    let mut defaulted = beta.defaulted.clone();
+    defaulted.sort();
+    assert_eq!(
+        defaulted,
+        vec![
+            "carousel-content.screenshot",
+            "carousel-content.thumbnail",
+            "description-text",
+            "downloads.linux.file_size",
+            "popularity",
+        ]
+    );
+    assert_eq!(beta.unexpected, vec!["brand-new-field"]);
+    assert!(beta.error.is_none());
+    let dropped: Vec<&str> = report
+        .dropped()
+        .iter()
+        .map(|p| p.machine_name.as_str())
+        .collect();
+    assert_eq!(dropped, vec!["gamma_trove"]);
+    assert!(!feed.is_complete());
+
+    // A partial feed must not mark anything as removed.
+    let mut trove = new_trove(&dir);
+    let earlier = load_feed(
+        &dir,
+        "earlier.json",
+        "2020-04-01T17:00:00.000000",
+        vec![product("epsilon_trove", "Epsilon", "epsilon.exe", "e", 1)],
+    );
+    trove.add_games(earlier);
+    trove.add_games(feed);
+    assert_eq!(trove.games.len(), 4);
+    assert!(trove.games.iter().all(|g| !g.removed_from_trove));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn defaulted_md5_and_size_are_not_installer_updates() {
+    let dir = temp_dir("defaulted");
+    let mut broken = product("alpha_trove", "Alpha", "alpha.exe", "", 0);
+    broken["downloads"]["windows"]["md5"] = Value::Null;
+    broken["downloads"]["windows"]["file_size"] = json!("2 B");
+    let mut trove = new_trove(&dir);
+    trove.add_games(load_feed(
+        &dir,
+        "older.json",
+        "2020-05-01T17:00:00.000000",
+        vec![product("alpha_trove", "Alpha", "alpha.exe", "a", 2)],
+    ));
+    trove.add_games(load_feed(
+        &dir,
+        "newer.json",
+        "2020-06-01T17:00:00.000000",
+        vec![broken],
+    ));
+    let alpha = game(&trove, "alpha_trove");
+    assert!(alpha.previous_installers.is_empty());
+    assert_eq!(alpha.installers["windows"].md5, "a");
+    assert_eq!(alpha.installers["windows"].file_size, 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn diff_matches_products_by_machine_name() {
+    let dir = temp_dir("diff");
+    let older = load_feed(
+        &dir,
+        "older.json",
+        "2020-05-01T17:00:00.000000",
+        vec![
+            product("alpha_trove", "Alpha", "alpha.exe", "a", 1),
+            product("beta_trove", "Beta", "beta.exe", "b", 1),
+        ],
+    );
+    let newer = load_feed(
+        &dir,
+        "newer.json",
+        "2020-06-01T17:00:00.000000",
+        vec![
+            product("alpha_trove", "Alpha™", "alpha.exe", "a2", 1),
+            product("gamma_trove", "Gamma", "gamma.exe", "c", 1),
+        ],
+    );
+
+    let diff = newer.diff(&older);
+    let added: Vec<&str> = diff.added.iter().map(|p| p.machine_name.as_str()).collect();
+    let removed: Vec<&str> = diff
+        .removed
+        .iter()
+        .map(|p| p.machine_name.as_str())
+        .collect();
+    assert_eq!(added, vec!["gamma_trove"]);
+    assert_eq!(removed, vec!["beta_trove"]);
+    assert_eq!(diff.changed.len(), 1);
+    let alpha = &diff.changed[0];
+    assert_eq!(alpha.machine_name, "alpha_trove");
+    let changes: Vec<(&str, Option<&str>, Option<&str>)> = alpha
+        .changes
+        .iter()
+        .map(|c| (c.field.as_str(), c.old.as_deref(), c.new.as_deref()))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            ("human_name", Some("Alpha"), Some("Alpha™")),
+            ("downloads.windows.md5", Some("a"), Some("a2")),
+        ]
+    );
+    assert_eq!(diff.installer_updates.len(), 1);
+    assert_eq!(diff.installer_updates[0].old.md5, "a");
+    assert_eq!(diff.installer_updates[0].new.md5, "a2");
+    assert!(newer.diff(&newer).is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}